@@ -0,0 +1,251 @@
+//! Evaluation of ETA estimators against recorded traces
+//!
+//! In a trace of deletions that finished, we know when each process exited, so we can compare
+//! the completion time each estimator predicted at each frame with the real one. Estimators
+//! count either whole operands, or also the fraction of the current operand's tree since the
+//! trace began, from the directory listings recorded in it.
+
+use crate::{
+    procfs::ProcSource,
+    progress::{Estimate, Progress, RateTracker, Sampler},
+    trace::Frame,
+};
+use std::{collections::HashMap, fmt, time::Duration};
+
+/// How the rate is computed from successive samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rate {
+    /// Extrapolation of the average rate since the process started
+    Linear,
+    /// Moving average of the rate, as when watching
+    Windowed(Duration),
+}
+
+/// A way to compute the ETA from successive samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimator {
+    pub rate: Rate,
+    /// Count the fraction of the current operand's tree, not only whole operands. It is only
+    /// known since the trace began, so the linear rate ignores it.
+    pub tree: bool,
+}
+
+impl Estimator {
+    /// The estimators worth comparing
+    pub fn all() -> Vec<Self> {
+        let windows = [
+            Duration::from_secs(10),
+            RateTracker::DEFAULT_WINDOW,
+            Duration::from_secs(300),
+        ];
+        let windowed = [false, true]
+            .into_iter()
+            .flat_map(|tree| windows.map(|window| (Rate::Windowed(window), tree)));
+        [(Rate::Linear, false)]
+            .into_iter()
+            .chain(windowed)
+            .map(|(rate, tree)| Estimator { rate, tree })
+            .collect()
+    }
+}
+
+impl fmt::Display for Estimator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.rate {
+            Rate::Linear => write!(f, "linear")?,
+            Rate::Windowed(window) => write!(f, "windowed {}s", window.as_secs_f32())?,
+        }
+        if self.tree {
+            write!(f, " +tree")?;
+        }
+        Ok(())
+    }
+}
+
+/// How far off an estimate was
+#[derive(Debug, Clone)]
+pub struct EtaError {
+    pub pid: u32,
+    /// Fraction of the process's lifetime elapsed when sampled
+    pub lifetime_fraction: f64,
+    /// Predicted minus real completion time in seconds, if there was an ETA
+    pub error: Option<f64>,
+}
+
+/// Errors of one estimator over a trace
+#[derive(Debug, Clone)]
+pub struct Backtest {
+    pub estimator: Estimator,
+    pub errors: Vec<EtaError>,
+}
+
+impl Backtest {
+    /// Errors of the samples that had an ETA
+    fn known_errors(&self) -> impl Iterator<Item = f64> + '_ {
+        self.errors.iter().filter_map(|e| e.error)
+    }
+
+    pub fn with_eta(&self) -> usize {
+        self.known_errors().count()
+    }
+
+    pub fn mean_absolute_error(&self) -> Option<f64> {
+        mean(self.known_errors().map(f64::abs))
+    }
+
+    /// Mean signed error: positive if the estimator is pessimistic
+    pub fn bias(&self) -> Option<f64> {
+        mean(self.known_errors())
+    }
+
+    /// Mean absolute error of the samples in each tenth of the process lifetimes
+    pub fn mean_absolute_error_over_time(&self) -> [Option<f64>; 10] {
+        std::array::from_fn(|decile| {
+            mean(
+                self.errors
+                    .iter()
+                    .filter(|e| ((e.lifetime_fraction * 10.0) as usize).min(9) == decile)
+                    .filter_map(|e| e.error.map(f64::abs)),
+            )
+        })
+    }
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// Time since boot at which each process exited during the trace.
+///
+/// A process exited between the last frame it appears in and the next one, so we take the
+/// middle. Processes still running at the end of the trace are left out, as are those whose
+/// next frame has an earlier clock, which only a broken trace has.
+pub fn completion_times(frames: &[Frame]) -> HashMap<u32, Duration> {
+    let mut last_seen: HashMap<u32, usize> = HashMap::new();
+    for (i, frame) in frames.iter().enumerate() {
+        for &pid in frame.processes.processes.keys() {
+            last_seen.insert(pid, i);
+        }
+    }
+    last_seen
+        .into_iter()
+        .filter_map(|(pid, i)| {
+            let seen = frames[i].clock.since_boot;
+            let gone = frames.get(i + 1)?.clock.since_boot;
+            Some((pid, seen + gone.checked_sub(seen)? / 2))
+        })
+        .collect()
+}
+
+/// Run the estimators over the frames of a trace
+pub fn backtest(frames: &[Frame], estimators: &[Estimator]) -> Vec<Backtest> {
+    let ends = completion_times(frames);
+    let mut results: Vec<Backtest> = estimators
+        .iter()
+        .map(|&estimator| Backtest {
+            estimator,
+            errors: Vec::new(),
+        })
+        .collect();
+    let mut trackers: HashMap<(usize, u32), RateTracker> = HashMap::new();
+    let Some(first) = frames.first() else {
+        return results;
+    };
+    // Kept across frames, for the directory listings as first seen
+    let mut sampler = Sampler::new(&first.processes, &first.clock);
+    sampler.probe_filesystem = false;
+    for frame in frames {
+        sampler.source = &frame.processes;
+        sampler.clock = &frame.clock;
+        for pid in frame.processes.pids().unwrap_or_default() {
+            let Some(&end) = ends.get(&pid) else {
+                continue;
+            };
+            let Ok(progress) = sampler.sample(pid) else {
+                continue;
+            };
+            let start = progress
+                .sampled_at
+                .saturating_sub(progress.time_since_start);
+            let lifetime = end.saturating_sub(start).as_secs_f64();
+            let lifetime_fraction = if lifetime > 0.0 {
+                progress.time_since_start.as_secs_f64() / lifetime
+            } else {
+                1.0
+            };
+            let whole_operands = Progress {
+                intra: None,
+                ..progress.clone()
+            };
+            for (i, result) in results.iter_mut().enumerate() {
+                let progress = if result.estimator.tree {
+                    &progress
+                } else {
+                    &whole_operands
+                };
+                let recent_rate = match result.estimator.rate {
+                    Rate::Linear => None,
+                    Rate::Windowed(window) => match trackers.get_mut(&(i, pid)) {
+                        Some(tracker) => tracker.update(progress),
+                        None => {
+                            trackers.insert((i, pid), RateTracker::with_window(progress, window));
+                            None
+                        }
+                    },
+                };
+                let estimate = Estimate::new(progress, recent_rate);
+                result.errors.push(EtaError {
+                    pid,
+                    lifetime_fraction,
+                    error: estimate
+                        .eta
+                        .and_then(|eta| progress.sampled_at.checked_add(eta))
+                        .map(|predicted| predicted.as_secs_f64() - end.as_secs_f64()),
+                });
+            }
+        }
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::FixedClock,
+        procfs::{FakeProc, FakeProcess},
+    };
+    use std::time::UNIX_EPOCH;
+
+    fn frame(since_boot: u64, pids: &[u32]) -> Frame {
+        let mut processes = FakeProc::default();
+        for &pid in pids {
+            processes.insert(pid, FakeProcess::default());
+        }
+        Frame {
+            clock: FixedClock {
+                since_boot: Duration::from_secs(since_boot),
+                ticks_per_second: 100,
+                wall: UNIX_EPOCH,
+            },
+            processes,
+        }
+    }
+
+    #[test]
+    fn completion_between_frames() {
+        let frames = [frame(10, &[1, 2]), frame(20, &[2]), frame(30, &[2])];
+        let ends = completion_times(&frames);
+        assert_eq!(ends.get(&1), Some(&Duration::from_secs(15)));
+        // Still running
+        assert_eq!(ends.get(&2), None);
+    }
+
+    #[test]
+    fn clock_going_backwards() {
+        let frames = [frame(100, &[1]), frame(50, &[])];
+        assert!(completion_times(&frames).is_empty());
+        assert!(backtest(&frames, &Estimator::all())[0].errors.is_empty());
+    }
+}
@@ -0,0 +1,183 @@
+//! Load of the block device rm is deleting from
+//!
+//! The filesystem of a path is mapped to its backing device through `/proc/self/mountinfo` and
+//! `/sys/class/block`. Sampling the device's `stat` file tells whether it is saturated, the way
+//! `iostat` does.
+
+use crate::{error::Error, path::display_path};
+use nix::sys::stat::{major, minor};
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+const CLASS_BLOCK: &str = "/sys/class/block";
+
+/// A whole disk, or a virtual device like a device-mapper or md one
+#[derive(Debug, Clone)]
+pub struct BlockDevice {
+    pub name: String,
+    /// Unknown if sysfs doesn't tell
+    pub rotational: Option<bool>,
+    sys: PathBuf,
+}
+
+/// Name of the block device with a given `major:minor` number, in sysfs
+fn device_by_number(number: &str) -> Option<String> {
+    fs::read_dir(CLASS_BLOCK)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find(|entry| {
+            fs::read_to_string(entry.path().join("dev")).is_ok_and(|dev| dev.trim() == number)
+        })
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+}
+
+/// Mount source of the deepest mount containing a path, with a given `major:minor` number.
+///
+/// Needed for filesystems like btrfs, whose device numbers aren't those of a block device.
+fn mount_source(number: &str, path: &Path) -> Option<String> {
+    let mountinfo = fs::read_to_string(MOUNTINFO).ok()?;
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            // id parent major:minor root mount-point options... - type source super-options
+            let (fields, rest) = line.split_once(" - ")?;
+            let fields: Vec<&str> = fields.split(' ').collect();
+            let mount_point = Path::new(fields.get(4)?);
+            let source = rest.split(' ').nth(1)?;
+            (fields.get(2) == Some(&number) && path.starts_with(mount_point))
+                .then(|| (mount_point.components().count(), source.to_string()))
+        })
+        .max_by_key(|(depth, _)| *depth)
+        .map(|(_, source)| source)
+}
+
+impl BlockDevice {
+    /// Device holding a path. If the path was already removed, its closest existing ancestor
+    /// is used.
+    pub fn of(path: &Path) -> Result<Self, Error> {
+        let (path, metadata) = path
+            .ancestors()
+            .find_map(|ancestor| Some((ancestor, ancestor.symlink_metadata().ok()?)))
+            .ok_or_else(|| Error::System(format!("no device for {}", display_path(path))))?;
+        let number = format!("{}:{}", major(metadata.dev()), minor(metadata.dev()));
+        let name = device_by_number(&number)
+            .or_else(|| {
+                let source = mount_source(&number, path)?;
+                let name = Path::new(&source)
+                    .file_name()?
+                    .to_string_lossy()
+                    .into_owned();
+                Path::new(CLASS_BLOCK).join(&name).exists().then_some(name)
+            })
+            .ok_or_else(|| {
+                Error::System(format!(
+                    "no block device behind {} ({number})",
+                    display_path(path)
+                ))
+            })?;
+        // Partitions share the statistics and queue of their disk
+        let mut sys = fs::canonicalize(Path::new(CLASS_BLOCK).join(&name))
+            .map_err(|e| Error::System(format!("block device {name}: {e}")))?;
+        if sys.join("partition").exists() {
+            sys.pop();
+        }
+        let rotational = fs::read_to_string(sys.join("queue/rotational"))
+            .ok()
+            .map(|rotational| rotational.trim() == "1");
+        Ok(Self {
+            name: sys
+                .file_name()
+                .map_or(name, |name| name.to_string_lossy().into_owned()),
+            rotational,
+            sys,
+        })
+    }
+
+    pub fn stat(&self) -> Result<DiskStat, Error> {
+        let stat = fs::read_to_string(self.sys.join("stat"))
+            .map_err(|e| Error::System(format!("block device {}: {e}", self.name)))?;
+        let fields: Vec<u64> = stat
+            .split_whitespace()
+            .map(|field| field.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|e| Error::Parse(format!("block device {} stat: {e}", self.name)))?;
+        // See Documentation/block/stat.rst
+        let field = |i: usize| {
+            fields
+                .get(i)
+                .copied()
+                .ok_or_else(|| Error::Parse(format!("block device {} stat: too short", self.name)))
+        };
+        Ok(DiskStat {
+            reads: field(0)?,
+            writes: field(4)?,
+            in_flight: field(8)?,
+            io_ticks: Duration::from_millis(field(9)?),
+            time_in_queue: Duration::from_millis(field(10)?),
+        })
+    }
+}
+
+/// Cumulative counters of a block device
+#[derive(Debug, Clone, Copy)]
+pub struct DiskStat {
+    pub reads: u64,
+    pub writes: u64,
+    /// Requests currently in flight
+    pub in_flight: u64,
+    /// Time the device was busy
+    pub io_ticks: Duration,
+    /// Sum of the time requests spent waiting and in service
+    pub time_in_queue: Duration,
+}
+
+/// Activity of a device between two samples
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceLoad {
+    /// Fraction of the time the device was busy
+    pub utilization: f32,
+    /// Average number of requests waiting or in service
+    pub queue_depth: f32,
+    pub in_flight: u64,
+    pub read_iops: f32,
+    pub write_iops: f32,
+}
+
+/// Follows the load of a device between successive samples
+pub struct DeviceTracker {
+    pub device: BlockDevice,
+    last: Option<(Duration, DiskStat)>,
+}
+
+impl DeviceTracker {
+    pub fn new(device: BlockDevice) -> Self {
+        Self { device, last: None }
+    }
+
+    /// Sample the device at a given time since boot. The load is known from the second sample.
+    pub fn update(&mut self, sampled_at: Duration) -> Result<Option<DeviceLoad>, Error> {
+        let stat = self.device.stat()?;
+        let load = self.last.and_then(|(last_sample, last)| {
+            let elapsed = sampled_at.checked_sub(last_sample)?.as_secs_f32();
+            (elapsed > 0.0).then(|| DeviceLoad {
+                utilization: (stat.io_ticks.saturating_sub(last.io_ticks).as_secs_f32() / elapsed)
+                    .min(1.0),
+                queue_depth: stat
+                    .time_in_queue
+                    .saturating_sub(last.time_in_queue)
+                    .as_secs_f32()
+                    / elapsed,
+                in_flight: stat.in_flight,
+                read_iops: stat.reads.saturating_sub(last.reads) as f32 / elapsed,
+                write_iops: stat.writes.saturating_sub(last.writes) as f32 / elapsed,
+            })
+        });
+        self.last = Some((sampled_at, stat));
+        Ok(load)
+    }
+}
@@ -0,0 +1,88 @@
+//! Time sources for elapsed time computations
+//!
+//! Process start times in procfs are in clock ticks since boot, so we need both the time
+//! since boot and the tick frequency. [`FixedClock`] makes estimations deterministic.
+
+use crate::error::Error;
+use std::time::{Duration, SystemTime};
+
+pub trait Clock {
+    /// Time since boot, including time spent suspended (`CLOCK_BOOTTIME`)
+    fn since_boot(&self) -> Result<Duration, Error>;
+    /// Clock ticks per second (`CLK_TCK`), the unit of times in procfs
+    fn ticks_per_second(&self) -> Result<u64, Error>;
+    /// Wall clock time, for display
+    fn wall(&self) -> SystemTime;
+}
+
+/// The system clock
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn since_boot(&self) -> Result<Duration, Error> {
+        let now = nix::time::clock_gettime(nix::time::ClockId::CLOCK_BOOTTIME)
+            .map_err(|e| Error::System(format!("cannot get time: {e}")))?;
+        Ok(Duration::from(now))
+    }
+
+    fn ticks_per_second(&self) -> Result<u64, Error> {
+        nix::unistd::sysconf(nix::unistd::SysconfVar::CLK_TCK)
+            .map_err(|e| Error::System(format!("Cannot get system clock ticks: {e}")))?
+            .ok_or_else(|| Error::System("empty clock tick".to_string()))?
+            .try_into()
+            .map_err(|e| Error::System(format!("negative clock ticks: {e}")))
+    }
+
+    fn wall(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock stopped at a given time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedClock {
+    pub since_boot: Duration,
+    pub ticks_per_second: u64,
+    pub wall: SystemTime,
+}
+
+impl Clock for FixedClock {
+    fn since_boot(&self) -> Result<Duration, Error> {
+        Ok(self.since_boot)
+    }
+
+    fn ticks_per_second(&self) -> Result<u64, Error> {
+        Ok(self.ticks_per_second)
+    }
+
+    fn wall(&self) -> SystemTime {
+        self.wall
+    }
+}
+
+/// Convert clock ticks to a duration, without losing sub-second precision
+pub fn ticks_to_duration(ticks: u64, ticks_per_second: u64) -> Duration {
+    if ticks_per_second == 0 {
+        return Duration::ZERO;
+    }
+    let nanos = (ticks % ticks_per_second) * 1_000_000_000 / ticks_per_second;
+    Duration::from_secs(ticks / ticks_per_second) + Duration::from_nanos(nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_keep_sub_second_precision() {
+        assert_eq!(ticks_to_duration(250, 100), Duration::from_millis(2500));
+        assert_eq!(ticks_to_duration(1, 3), Duration::from_nanos(333_333_333));
+        assert_eq!(ticks_to_duration(u64::MAX, 100).as_secs(), u64::MAX / 100);
+    }
+
+    #[test]
+    fn no_ticks_per_second() {
+        assert_eq!(ticks_to_duration(250, 0), Duration::ZERO);
+    }
+}
@@ -0,0 +1,568 @@
+//! rm, find, xargs and chmod-like command line parsing
+//!
+//! For rm and the tools walking their operands like it, this follows getopt_long semantics as
+//! used by GNU coreutils, uutils and busybox: options may be grouped (`-rf`) and appear after
+//! operands, long options may be abbreviated to an unambiguous prefix, and `--` ends option
+//! processing.
+//!
+//! Arguments are handled as raw bytes, since operands need not be valid UTF-8.
+
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::Path,
+};
+
+/// When rm prompts before removal
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Interactive {
+    #[default]
+    Never,
+    Once,
+    Always,
+}
+
+/// Protection of `/` from recursive removal
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PreserveRoot {
+    No,
+    #[default]
+    Yes,
+    /// Also reject operands on a different device than their parent
+    All,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RmFlags {
+    /// Ignore nonexistent files and arguments
+    pub force: bool,
+    pub interactive: Interactive,
+    pub recursive: bool,
+    /// Remove empty directories
+    pub dir: bool,
+    pub verbose: bool,
+    pub one_file_system: bool,
+    pub preserve_root: PreserveRoot,
+}
+
+/// A parsed rm command line
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RmInvocation {
+    pub flags: RmFlags,
+    /// Files to remove, in the order rm processes them
+    pub operands: Vec<OsString>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HasArg {
+    No,
+    /// Value can only be given with `--option=value`
+    Optional,
+    /// Value is given with `--option=value` or as the next argument
+    Required,
+}
+
+const LONG_OPTIONS: &[(&str, HasArg)] = &[
+    ("force", HasArg::No),
+    ("interactive", HasArg::Optional),
+    ("one-file-system", HasArg::No),
+    ("no-preserve-root", HasArg::No),
+    ("preserve-root", HasArg::Optional),
+    ("recursive", HasArg::No),
+    ("dir", HasArg::No),
+    ("verbose", HasArg::No),
+    ("help", HasArg::No),
+    ("version", HasArg::No),
+    // uutils only
+    ("presume-input-tty", HasArg::No),
+];
+
+/// A parsed find command line, as far as deletion goes
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FindInvocation {
+    /// Trees to walk, in the order find processes them
+    pub starting_points: Vec<OsString>,
+    /// The expression has `-delete`, or runs rm with `-exec` and the like
+    pub deletes: bool,
+}
+
+impl RmInvocation {
+    /// Parse rm arguments, not including the command name.
+    ///
+    /// The process is already running, so rm accepted its command line: unknown options
+    /// are ignored rather than rejected, since they most likely come from another rm flavour.
+    pub fn parse<I, S>(args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut invocation = RmInvocation::default();
+        let mut options_done = false;
+        for arg in args {
+            let arg = arg.as_ref();
+            let bytes = arg.as_bytes();
+            if options_done || bytes == b"-" || !bytes.starts_with(b"-") {
+                invocation.operands.push(arg.to_owned());
+            } else if bytes == b"--" {
+                options_done = true;
+            } else if let Some(long) = bytes.strip_prefix(b"--") {
+                invocation.flags.apply_long(long);
+            } else {
+                for &c in &bytes[1..] {
+                    invocation.flags.apply_short(c);
+                }
+            }
+        }
+        invocation
+    }
+}
+
+impl RmFlags {
+    fn apply_short(&mut self, c: u8) {
+        match c {
+            b'f' => {
+                self.force = true;
+                self.interactive = Interactive::Never;
+            }
+            b'i' => {
+                self.force = false;
+                self.interactive = Interactive::Always;
+            }
+            b'I' => {
+                self.force = false;
+                self.interactive = Interactive::Once;
+            }
+            b'r' | b'R' => self.recursive = true,
+            b'd' => self.dir = true,
+            b'v' => self.verbose = true,
+            _ => {}
+        }
+    }
+
+    fn apply_long(&mut self, option: &[u8]) {
+        let (name, value) = match option.iter().position(|&c| c == b'=') {
+            Some(eq) => (&option[..eq], Some(&option[eq + 1..])),
+            None => (option, None),
+        };
+        // Option names are ASCII, anything else is unknown
+        let Some((name, has_arg)) = std::str::from_utf8(name)
+            .ok()
+            .and_then(|name| lookup_long(LONG_OPTIONS, name))
+        else {
+            return;
+        };
+        let value = if has_arg == HasArg::No { None } else { value };
+        match name {
+            "force" => self.apply_short(b'f'),
+            "interactive" => match value {
+                None | Some(b"always" | b"yes") => self.apply_short(b'i'),
+                Some(b"once") => self.apply_short(b'I'),
+                Some(b"never" | b"no" | b"none") => self.interactive = Interactive::Never,
+                Some(_) => {}
+            },
+            "one-file-system" => self.one_file_system = true,
+            "no-preserve-root" => self.preserve_root = PreserveRoot::No,
+            "preserve-root" => {
+                self.preserve_root = match value {
+                    Some(b"all") => PreserveRoot::All,
+                    _ => PreserveRoot::Yes,
+                }
+            }
+            "recursive" => self.recursive = true,
+            "dir" => self.dir = true,
+            "verbose" => self.verbose = true,
+            _ => {}
+        }
+    }
+}
+
+/// Find a long option by its full name or an unambiguous prefix
+fn lookup_long(options: &[(&'static str, HasArg)], name: &str) -> Option<(&'static str, HasArg)> {
+    if let Some(&option) = options.iter().find(|(long, _)| *long == name) {
+        return Some(option);
+    }
+    let mut candidates = options.iter().filter(|(long, _)| long.starts_with(name));
+    match (candidates.next(), candidates.next()) {
+        (Some(&option), None) => Some(option),
+        _ => None,
+    }
+}
+
+/// Command line syntax of a tool that walks its operands in order, like `rm -r`
+#[derive(Debug, Clone, Copy)]
+pub struct WalkSyntax {
+    /// Short options that take a value
+    short_with_arg: &'static [u8],
+    long_options: &'static [(&'static str, HasArg)],
+    /// Arguments before the operands, like the mode of chmod
+    leading: usize,
+    /// Takes `-rwx` and the like as a mode, like chmod
+    dash_modes: bool,
+}
+
+pub const CHMOD: WalkSyntax = WalkSyntax {
+    short_with_arg: b"",
+    long_options: &[
+        ("changes", HasArg::No),
+        ("no-preserve-root", HasArg::No),
+        ("preserve-root", HasArg::No),
+        ("quiet", HasArg::No),
+        ("silent", HasArg::No),
+        ("reference", HasArg::Required),
+        ("recursive", HasArg::No),
+        ("verbose", HasArg::No),
+        ("help", HasArg::No),
+        ("version", HasArg::No),
+    ],
+    leading: 1,
+    dash_modes: true,
+};
+
+/// chown and chgrp, which take an owner or a group
+pub const CHOWN: WalkSyntax = WalkSyntax {
+    short_with_arg: b"",
+    long_options: &[
+        ("changes", HasArg::No),
+        ("dereference", HasArg::No),
+        ("no-dereference", HasArg::No),
+        ("from", HasArg::Required),
+        ("no-preserve-root", HasArg::No),
+        ("preserve-root", HasArg::No),
+        ("quiet", HasArg::No),
+        ("silent", HasArg::No),
+        ("reference", HasArg::Required),
+        ("recursive", HasArg::No),
+        ("verbose", HasArg::No),
+        ("help", HasArg::No),
+        ("version", HasArg::No),
+    ],
+    leading: 1,
+    dash_modes: false,
+};
+
+/// setfacl, whose ACL entries come as option values
+pub const SETFACL: WalkSyntax = WalkSyntax {
+    short_with_arg: b"mMxX",
+    long_options: &[
+        ("modify", HasArg::Required),
+        ("modify-file", HasArg::Required),
+        ("remove", HasArg::Required),
+        ("remove-file", HasArg::Required),
+        ("remove-all", HasArg::No),
+        ("remove-default", HasArg::No),
+        ("set", HasArg::Required),
+        ("set-file", HasArg::Required),
+        ("mask", HasArg::No),
+        ("no-mask", HasArg::No),
+        ("default", HasArg::No),
+        ("restore", HasArg::Required),
+        ("test", HasArg::No),
+        ("logical", HasArg::No),
+        ("physical", HasArg::No),
+        ("recursive", HasArg::No),
+        ("help", HasArg::No),
+        ("version", HasArg::No),
+    ],
+    leading: 0,
+    dash_modes: false,
+};
+
+/// A parsed command line of a tool walking its operands, like chmod, chown, chgrp or setfacl
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct WalkInvocation {
+    /// `-R`: operands are walked recursively
+    pub recursive: bool,
+    /// Files to process, in order
+    pub operands: Vec<OsString>,
+}
+
+/// A parsed xargs command line, as far as its input goes
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct XargsInvocation {
+    /// File to read arguments from instead of stdin, with `-a`
+    pub arg_file: Option<OsString>,
+}
+
+/// xargs short options that take a value
+const XARGS_SHORT_WITH_ARG: &[u8] = b"adEILnPs";
+
+/// Deprecated xargs short options with an optional value
+const XARGS_SHORT_OPTIONAL_ARG: &[u8] = b"eil";
+
+/// xargs long options that take a value, which can be a separate argument
+const XARGS_LONG_WITH_ARG: &[&str] = &[
+    "arg-file",
+    "delimiter",
+    "max-args",
+    "max-chars",
+    "max-procs",
+    "process-slot-var",
+];
+
+impl FindInvocation {
+    /// Parse find arguments, not including the command name.
+    ///
+    /// Like rm, find already accepted its command line, so this only looks for the starting
+    /// points and the actions that delete.
+    pub fn parse<I, S>(args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args: Vec<S> = args.into_iter().collect();
+        let args: Vec<&[u8]> = args.iter().map(|arg| arg.as_ref().as_bytes()).collect();
+        let mut invocation = FindInvocation::default();
+        let mut i = 0;
+        // Options before the starting points: -H, -L, -P, -D debugopts and -Olevel
+        while let Some(&arg) = args.get(i) {
+            match arg {
+                b"-H" | b"-L" | b"-P" => i += 1,
+                b"-D" => i += 2,
+                b"--" => {
+                    i += 1;
+                    break;
+                }
+                _ if arg.starts_with(b"-O") => i += 1,
+                _ => break,
+            }
+        }
+        // The expression starts at the first argument beginning with `-`, `(` or `!`
+        while let Some(&arg) = args.get(i) {
+            if arg.starts_with(b"-") || arg == b"(" || arg == b"!" {
+                break;
+            }
+            invocation
+                .starting_points
+                .push(OsStr::from_bytes(arg).to_owned());
+            i += 1;
+        }
+        if invocation.starting_points.is_empty() {
+            invocation.starting_points.push(OsString::from("."));
+        }
+        let expression = &args[i.min(args.len())..];
+        invocation.deletes = expression.iter().enumerate().any(|(i, &arg)| match arg {
+            b"-delete" => true,
+            b"-exec" | b"-execdir" | b"-ok" | b"-okdir" => expression
+                .get(i + 1)
+                .and_then(|command| Path::new(OsStr::from_bytes(command)).file_name())
+                .is_some_and(|name| name == "rm"),
+            _ => false,
+        });
+        invocation
+    }
+}
+
+impl XargsInvocation {
+    /// Parse xargs arguments, not including the command name. Options end at the command xargs
+    /// runs.
+    pub fn parse<I, S>(args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut invocation = XargsInvocation::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let bytes = arg.as_ref().as_bytes();
+            if bytes == b"--" || !bytes.starts_with(b"-") || bytes == b"-" {
+                break;
+            }
+            let (is_arg_file, value) = if let Some(long) = bytes.strip_prefix(b"--") {
+                let (name, value) = match long.iter().position(|&c| c == b'=') {
+                    Some(eq) => (&long[..eq], Some(&long[eq + 1..])),
+                    None => (long, None),
+                };
+                // Abbreviations are unlikely in scripts, only full names are recognized
+                let takes_arg = XARGS_LONG_WITH_ARG.iter().any(|o| o.as_bytes() == name);
+                let value = match value {
+                    Some(value) => Some(value.to_vec()),
+                    None if takes_arg => args.next().map(|a| a.as_ref().as_bytes().to_vec()),
+                    None => None,
+                };
+                (name == b"arg-file", value)
+            } else {
+                // The first option with a value takes the rest of the group, or the next
+                // argument. Optional values can only be in the rest of the group.
+                let Some(i) = bytes[1..].iter().position(|c| {
+                    XARGS_SHORT_WITH_ARG.contains(c) || XARGS_SHORT_OPTIONAL_ARG.contains(c)
+                }) else {
+                    continue;
+                };
+                if XARGS_SHORT_OPTIONAL_ARG.contains(&bytes[i + 1]) {
+                    continue;
+                }
+                let rest = &bytes[i + 2..];
+                let value = if rest.is_empty() {
+                    args.next().map(|a| a.as_ref().as_bytes().to_vec())
+                } else {
+                    Some(rest.to_vec())
+                };
+                (bytes[i + 1] == b'a', value)
+            };
+            if is_arg_file {
+                invocation.arg_file = value.map(OsString::from_vec);
+            }
+        }
+        invocation
+    }
+}
+
+impl WalkInvocation {
+    /// Parse arguments with a given syntax, not including the command name. Like for rm,
+    /// unknown options are ignored.
+    pub fn parse<I, S>(syntax: &WalkSyntax, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        const MODE_CHARS: &[u8] = b"rwxXstugoa,+=01234567";
+        let mut invocation = WalkInvocation::default();
+        let mut positional = Vec::new();
+        let mut leading = syntax.leading;
+        let mut options_done = false;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let arg = arg.as_ref();
+            let bytes = arg.as_bytes();
+            if options_done || bytes == b"-" || !bytes.starts_with(b"-") {
+                positional.push(arg.to_owned());
+            } else if bytes == b"--" {
+                options_done = true;
+            } else if let Some(long) = bytes.strip_prefix(b"--") {
+                let (name, value) = match long.iter().position(|&c| c == b'=') {
+                    Some(eq) => (&long[..eq], Some(&long[eq + 1..])),
+                    None => (long, None),
+                };
+                let Some((name, has_arg)) = std::str::from_utf8(name)
+                    .ok()
+                    .and_then(|name| lookup_long(syntax.long_options, name))
+                else {
+                    continue;
+                };
+                if has_arg == HasArg::Required && value.is_none() {
+                    args.next();
+                }
+                match name {
+                    "recursive" => invocation.recursive = true,
+                    // The reference file replaces the mode or owner
+                    "reference" => leading = 0,
+                    _ => {}
+                }
+            } else if syntax.dash_modes && MODE_CHARS.contains(&bytes[1]) {
+                positional.push(arg.to_owned());
+            } else {
+                for (i, &c) in bytes.iter().enumerate().skip(1) {
+                    if c == b'R' {
+                        invocation.recursive = true;
+                    }
+                    if syntax.short_with_arg.contains(&c) {
+                        // The value is the rest of the group, or the next argument
+                        if i + 1 == bytes.len() {
+                            args.next();
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        invocation.operands = positional.into_iter().skip(leading).collect();
+        invocation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operands(names: &[&str]) -> Vec<OsString> {
+        names.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn rm_flags_and_operands() {
+        let invocation = RmInvocation::parse(["-rf", "a", "--", "-b", "-"]);
+        assert!(invocation.flags.recursive);
+        assert!(invocation.flags.force);
+        assert_eq!(invocation.operands, operands(&["a", "-b", "-"]));
+    }
+
+    #[test]
+    fn rm_long_options() {
+        let invocation =
+            RmInvocation::parse(["a", "--rec", "--interactive=once", "--preserve=all"]);
+        assert!(invocation.flags.recursive);
+        assert_eq!(invocation.flags.interactive, Interactive::Once);
+        assert_eq!(invocation.flags.preserve_root, PreserveRoot::All);
+        assert_eq!(invocation.operands, operands(&["a"]));
+        // Ambiguous between --preserve-root and --presume-input-tty
+        let invocation = RmInvocation::parse(["--no-preserve-root", "--pres=all"]);
+        assert_eq!(invocation.flags.preserve_root, PreserveRoot::No);
+    }
+
+    #[test]
+    fn rm_non_utf8_operand() {
+        let operand = OsString::from_vec(b"caf\xe9".to_vec());
+        let invocation = RmInvocation::parse([OsString::from("-r"), operand.clone()]);
+        assert_eq!(invocation.operands, vec![operand]);
+    }
+
+    #[test]
+    fn find_starting_points() {
+        let invocation = FindInvocation::parse(["-L", "/srv", "/tmp", "-name", "*.tmp", "-delete"]);
+        assert_eq!(invocation.starting_points, operands(&["/srv", "/tmp"]));
+        assert!(invocation.deletes);
+        let invocation = FindInvocation::parse(["(", "-type", "f", ")", "-print"]);
+        assert_eq!(invocation.starting_points, operands(&["."]));
+        assert!(!invocation.deletes);
+    }
+
+    #[test]
+    fn find_exec_rm() {
+        assert!(FindInvocation::parse([".", "-exec", "/bin/rm", "{}", "+"]).deletes);
+        assert!(FindInvocation::parse([".", "-okdir", "rm", "{}", ";"]).deletes);
+        assert!(!FindInvocation::parse([".", "-exec", "echo", "rm", ";"]).deletes);
+    }
+
+    #[test]
+    fn xargs_arg_file() {
+        let arg_file = |args: &[&str]| XargsInvocation::parse(args).arg_file;
+        assert_eq!(arg_file(&["-0", "-a", "list", "rm"]), Some("list".into()));
+        assert_eq!(arg_file(&["-0alist", "rm"]), Some("list".into()));
+        assert_eq!(arg_file(&["--arg-file=list", "rm"]), Some("list".into()));
+        assert_eq!(arg_file(&["--arg-file", "list", "rm"]), Some("list".into()));
+        // Options of the command
+        assert_eq!(arg_file(&["-n", "10", "rm", "-a", "list"]), None);
+        // Optional value, in the same argument only
+        assert_eq!(arg_file(&["-eEOF", "rm"]), None);
+    }
+
+    #[test]
+    fn chmod_operands() {
+        let invocation = WalkInvocation::parse(&CHMOD, ["-R", "u+w", "a", "b"]);
+        assert!(invocation.recursive);
+        assert_eq!(invocation.operands, operands(&["a", "b"]));
+        let invocation = WalkInvocation::parse(&CHMOD, ["-w", "-R", "a"]);
+        assert_eq!(invocation.operands, operands(&["a"]));
+        let invocation = WalkInvocation::parse(&CHMOD, ["--reference", "ref", "-R", "a"]);
+        assert_eq!(invocation.operands, operands(&["a"]));
+    }
+
+    #[test]
+    fn chown_operands() {
+        let invocation = WalkInvocation::parse(&CHOWN, ["-R", "--from", "x:y", "user:", "a"]);
+        assert!(invocation.recursive);
+        assert_eq!(invocation.operands, operands(&["a"]));
+        let invocation = WalkInvocation::parse(&CHOWN, ["user", "a"]);
+        assert!(!invocation.recursive);
+    }
+
+    #[test]
+    fn setfacl_operands() {
+        let invocation = WalkInvocation::parse(&SETFACL, ["-R", "-m", "u:x:rw", "a", "b"]);
+        assert!(invocation.recursive);
+        assert_eq!(invocation.operands, operands(&["a", "b"]));
+        let invocation = WalkInvocation::parse(&SETFACL, ["-Rmu:x:rw", "a"]);
+        assert!(invocation.recursive);
+        assert_eq!(invocation.operands, operands(&["a"]));
+    }
+}
@@ -0,0 +1,313 @@
+//! Why a process is slow: scheduling state, CPU and I/O wait, I/O priority
+//!
+//! Most of it comes from `/proc/<pid>/stat`. Times are cumulative, so they are compared
+//! between two snapshots when watching, or to the process lifetime otherwise.
+
+use crate::{
+    clock::{Clock, ticks_to_duration},
+    error::Error,
+    procfs::{IOPRIO_CLASS_SHIFT, ProcSource, Stat},
+};
+use std::{fmt, time::Duration};
+
+/// I/O scheduling class, from `ioprio_get`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    /// No class set: best effort, with a level derived from the nice value
+    None,
+    RealTime(u8),
+    BestEffort(u8),
+    Idle,
+}
+
+impl IoClass {
+    fn from_ioprio(ioprio: i32) -> Option<Self> {
+        let level = (ioprio & ((1 << IOPRIO_CLASS_SHIFT) - 1)) as u8;
+        match ioprio >> IOPRIO_CLASS_SHIFT {
+            0 => Some(IoClass::None),
+            1 => Some(IoClass::RealTime(level)),
+            2 => Some(IoClass::BestEffort(level)),
+            3 => Some(IoClass::Idle),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for IoClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IoClass::None => write!(f, "default"),
+            IoClass::RealTime(level) => write!(f, "realtime/{level}"),
+            IoClass::BestEffort(level) => write!(f, "best-effort/{level}"),
+            IoClass::Idle => write!(f, "idle"),
+        }
+    }
+}
+
+/// I/O counters from `/proc/<pid>/io`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IoCounters {
+    /// Read syscalls
+    pub syscr: u64,
+    /// Write syscalls
+    pub syscw: u64,
+    /// Bytes fetched from storage
+    pub read_bytes: u64,
+    /// Bytes sent to storage
+    pub write_bytes: u64,
+}
+
+impl IoCounters {
+    fn parse(io: &str) -> Option<Self> {
+        let mut counters = Self::default();
+        let mut found = 0;
+        for line in io.lines() {
+            let (name, value) = line.split_once(':')?;
+            let field = match name {
+                "syscr" => &mut counters.syscr,
+                "syscw" => &mut counters.syscw,
+                "read_bytes" => &mut counters.read_bytes,
+                "write_bytes" => &mut counters.write_bytes,
+                _ => continue,
+            };
+            *field = value.trim().parse().ok()?;
+            found += 1;
+        }
+        (found == 4).then_some(counters)
+    }
+}
+
+/// Cumulative figures of a process at one point in time
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Time since boot
+    pub sampled_at: Duration,
+    /// `R` running, `S` sleeping, `D` uninterruptible wait (usually I/O)...
+    pub state: char,
+    pub nice: i64,
+    pub user_time: Duration,
+    pub system_time: Duration,
+    /// Time spent waiting for block I/O. Unknown if zero: delay accounting is likely disabled
+    /// (`kernel.task_delayacct` sysctl).
+    pub blkio_delay: Option<Duration>,
+    pub time_since_start: Duration,
+    pub io: Option<IoCounters>,
+    /// Kernel function the process is blocked in
+    pub wchan: Option<String>,
+    pub io_class: Option<IoClass>,
+}
+
+impl Snapshot {
+    pub fn take(source: &dyn ProcSource, clock: &dyn Clock, pid: u32) -> Result<Self, Error> {
+        let stat = Stat::read(source, pid)?;
+        let ticks_per_second = clock.ticks_per_second()?;
+        let ticks = |n: usize| -> Result<Duration, Error> {
+            Ok(ticks_to_duration(stat.number(n)?, ticks_per_second))
+        };
+        let start = ticks(22)?;
+        let sampled_at = clock.since_boot()?;
+        Ok(Self {
+            sampled_at,
+            state: stat.field(3)?.chars().next().unwrap_or('?'),
+            nice: stat.number(19)?,
+            user_time: ticks(14)?,
+            system_time: ticks(15)?,
+            // Missing on old kernels
+            blkio_delay: ticks(42).ok().filter(|delay| !delay.is_zero()),
+            time_since_start: sampled_at.saturating_sub(start),
+            io: source.io(pid).ok().and_then(|io| IoCounters::parse(&io)),
+            wchan: source
+                .wchan(pid)
+                .ok()
+                .map(|wchan| wchan.trim().to_string())
+                .filter(|wchan| !wchan.is_empty() && wchan != "0"),
+            io_class: source.ioprio(pid).ok().and_then(IoClass::from_ioprio),
+        })
+    }
+}
+
+/// Where a process spends its time
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    pub state: char,
+    pub nice: i64,
+    pub io_class: Option<IoClass>,
+    pub wchan: Option<String>,
+    /// Fraction of wall time on CPU, in user and system mode
+    pub cpu: f32,
+    /// Fraction of wall time blocked on I/O, if delay accounting is enabled
+    pub blocked_on_io: Option<f32>,
+    /// Read and write syscalls per second
+    pub syscalls_per_second: Option<(f32, f32)>,
+    /// Bytes read from and written to storage per second
+    pub bytes_per_second: Option<(f32, f32)>,
+}
+
+impl Diagnostics {
+    /// Compare with a previous snapshot, or since the process started
+    pub fn new(current: &Snapshot, previous: Option<&Snapshot>) -> Self {
+        let start = Snapshot {
+            sampled_at: current.sampled_at.saturating_sub(current.time_since_start),
+            user_time: Duration::ZERO,
+            system_time: Duration::ZERO,
+            blkio_delay: Some(Duration::ZERO),
+            io: Some(IoCounters::default()),
+            ..current.clone()
+        };
+        let previous = previous.unwrap_or(&start);
+        let elapsed = current
+            .sampled_at
+            .saturating_sub(previous.sampled_at)
+            .as_secs_f32();
+        let fraction = |now: Duration, before: Duration| {
+            if elapsed > 0.0 {
+                (now.saturating_sub(before).as_secs_f32() / elapsed).min(1.0)
+            } else {
+                0.0
+            }
+        };
+        let per_second = |now: u64, before: u64| now.saturating_sub(before) as f32 / elapsed;
+        let io = current.io.zip(previous.io).filter(|_| elapsed > 0.0);
+        Self {
+            state: current.state,
+            nice: current.nice,
+            io_class: current.io_class,
+            wchan: current.wchan.clone(),
+            cpu: fraction(
+                current.user_time + current.system_time,
+                previous.user_time + previous.system_time,
+            ),
+            blocked_on_io: current
+                .blkio_delay
+                .map(|now| fraction(now, previous.blkio_delay.unwrap_or_default())),
+            syscalls_per_second: io.map(|(now, before)| {
+                (
+                    per_second(now.syscr, before.syscr),
+                    per_second(now.syscw, before.syscw),
+                )
+            }),
+            bytes_per_second: io.map(|(now, before)| {
+                (
+                    per_second(now.read_bytes, before.read_bytes),
+                    per_second(now.write_bytes, before.write_bytes),
+                )
+            }),
+        }
+    }
+
+    /// A short explanation of what limits the process
+    pub fn explain(&self) -> String {
+        let mut reasons = Vec::new();
+        let blocked_on_io = self.blocked_on_io.unwrap_or_default();
+        if blocked_on_io >= 0.5 {
+            reasons.push(format!(
+                "{:.0}% of wall time blocked on IO",
+                blocked_on_io * 100.0
+            ));
+        } else if self.cpu >= 0.5 {
+            reasons.push(format!("{:.0}% of wall time on CPU", self.cpu * 100.0));
+        }
+        match self.io_class {
+            Some(IoClass::Idle) => reasons.push("idle ionice class".to_string()),
+            Some(IoClass::BestEffort(7)) => reasons.push("lowest best-effort ionice".to_string()),
+            _ => {}
+        }
+        if self.nice > 0 {
+            reasons.push(format!("nice {}", self.nice));
+        }
+        reasons.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(sampled_at: u64, cpu: u64, blkio_delay: u64, syscr: u64) -> Snapshot {
+        Snapshot {
+            sampled_at: Duration::from_secs(sampled_at),
+            state: 'D',
+            nice: 0,
+            user_time: Duration::from_secs(cpu),
+            system_time: Duration::ZERO,
+            blkio_delay: Some(Duration::from_secs(blkio_delay)).filter(|d| !d.is_zero()),
+            time_since_start: Duration::from_secs(sampled_at - 10),
+            io: Some(IoCounters {
+                syscr,
+                ..IoCounters::default()
+            }),
+            wchan: None,
+            io_class: None,
+        }
+    }
+
+    #[test]
+    fn io_class() {
+        assert_eq!(IoClass::from_ioprio(0), Some(IoClass::None));
+        assert_eq!(
+            IoClass::from_ioprio(1 << 13 | 2),
+            Some(IoClass::RealTime(2))
+        );
+        assert_eq!(
+            IoClass::from_ioprio(2 << 13 | 7),
+            Some(IoClass::BestEffort(7))
+        );
+        assert_eq!(IoClass::from_ioprio(3 << 13), Some(IoClass::Idle));
+        assert_eq!(IoClass::from_ioprio(4 << 13), None);
+    }
+
+    #[test]
+    fn io_counters() {
+        let io = "rchar: 1\nwchar: 2\nsyscr: 3\nsyscw: 4\nread_bytes: 5\nwrite_bytes: 6\n\
+                  cancelled_write_bytes: 0\n";
+        let counters = IoCounters::parse(io).unwrap();
+        assert_eq!(
+            (
+                counters.syscr,
+                counters.syscw,
+                counters.read_bytes,
+                counters.write_bytes
+            ),
+            (3, 4, 5, 6)
+        );
+        // Without the storage counters, as in some containers
+        assert!(IoCounters::parse("syscr: 3\nsyscw: 4\n").is_none());
+        assert!(IoCounters::parse("syscr: x\nsyscw: 4\nread_bytes: 5\nwrite_bytes: 6").is_none());
+    }
+
+    #[test]
+    fn since_start() {
+        // Started at 10s, 20s ago
+        let diagnostics = Diagnostics::new(&snapshot(30, 2, 15, 400), None);
+        assert_eq!(diagnostics.cpu, 0.1);
+        assert_eq!(diagnostics.blocked_on_io, Some(0.75));
+        assert_eq!(diagnostics.syscalls_per_second, Some((20.0, 0.0)));
+        assert_eq!(diagnostics.explain(), "75% of wall time blocked on IO");
+    }
+
+    #[test]
+    fn between_snapshots() {
+        let previous = snapshot(30, 2, 15, 400);
+        let mut current = snapshot(40, 8, 16, 500);
+        current.nice = 10;
+        current.io_class = Some(IoClass::Idle);
+        let diagnostics = Diagnostics::new(&current, Some(&previous));
+        assert_eq!(diagnostics.cpu, 0.6);
+        assert_eq!(diagnostics.blocked_on_io, Some(0.1));
+        assert_eq!(diagnostics.syscalls_per_second, Some((10.0, 0.0)));
+        assert_eq!(
+            diagnostics.explain(),
+            "60% of wall time on CPU, idle ionice class, nice 10"
+        );
+        // Delay accounting disabled
+        current.blkio_delay = None;
+        assert_eq!(
+            Diagnostics::new(&current, Some(&previous)).blocked_on_io,
+            None
+        );
+        // Same time as the previous snapshot
+        let diagnostics = Diagnostics::new(&previous, Some(&previous));
+        assert_eq!(diagnostics.cpu, 0.0);
+        assert_eq!(diagnostics.syscalls_per_second, None);
+    }
+}
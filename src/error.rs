@@ -0,0 +1,67 @@
+//! Errors from inspecting a process
+
+use std::{ffi::OsString, fmt, io};
+
+use crate::path::display_path;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The process exited while we were looking at it
+    ProcessVanished,
+    /// The process belongs to another user
+    PermissionDenied,
+    /// No open file matches an operand, and operands couldn't be checked for existence
+    NoMatchingFd,
+    /// A find process that doesn't delete anything
+    NotDeleting,
+    /// A chmod-like process that doesn't walk the trees of its operands
+    NotRecursive,
+    /// An operand has `..` components going above the root directory
+    UnnormalizableOperand(OsString),
+    /// Unexpected procfs content
+    Parse(String),
+    /// Failure getting system information, like the clock
+    System(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ProcessVanished => write!(f, "process exited"),
+            Error::PermissionDenied => write!(f, "permission denied"),
+            Error::NoMatchingFd => write!(f, "no open file matches an operand"),
+            Error::NotDeleting => write!(f, "not deleting files"),
+            Error::NotRecursive => write!(f, "not walking directory trees"),
+            Error::UnnormalizableOperand(operand) => write!(
+                f,
+                "operand {} goes above the root directory",
+                display_path(operand.as_ref())
+            ),
+            Error::Parse(e) | Error::System(e) => write!(f, "{e}"),
+            Error::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Errors reading `/proc/<pid>` files tell what happened to the process
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => Error::ProcessVanished,
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied,
+            // Reading a file of a process that just exited
+            _ if e.raw_os_error() == Some(nix::libc::ESRCH) => Error::ProcessVanished,
+            _ => Error::Io(e),
+        }
+    }
+}
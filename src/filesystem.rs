@@ -0,0 +1,93 @@
+//! Deletion rate as counted by the filesystem
+//!
+//! As rm works, the number of used inodes drops and free blocks rise. Sampling `statvfs` on the
+//! filesystem rm is deleting from gives rates in inodes and bytes, though other writers on the
+//! same filesystem add to them.
+
+use crate::{
+    error::Error,
+    path::display_path,
+    progress::{MovingAverage, RateTracker},
+};
+use std::{path::Path, time::Duration};
+
+/// Inode and block usage of a filesystem
+#[derive(Debug, Clone, Copy)]
+pub struct FsUsage {
+    /// Identifies the filesystem, to tell when rm moves to another one
+    pub fsid: u64,
+    pub used_inodes: u64,
+    pub free_bytes: u64,
+}
+
+impl FsUsage {
+    /// Usage of the filesystem a path is on. If the path was already removed, its closest
+    /// existing ancestor is used.
+    pub fn of(path: &Path) -> Result<Self, Error> {
+        let path = path
+            .ancestors()
+            .find(|ancestor| ancestor.symlink_metadata().is_ok())
+            .ok_or_else(|| Error::System(format!("no filesystem for {}", display_path(path))))?;
+        let stat = nix::sys::statvfs::statvfs(path)
+            .map_err(|e| Error::System(format!("statvfs {}: {e}", display_path(path))))?;
+        Ok(Self {
+            fsid: stat.filesystem_id(),
+            used_inodes: stat.files().saturating_sub(stat.files_free()),
+            free_bytes: stat.blocks_free().saturating_mul(stat.fragment_size()),
+        })
+    }
+}
+
+/// What is freed per second. Negative when other writers allocate more.
+#[derive(Debug, Clone, Copy)]
+pub struct FsRate {
+    pub inodes_per_second: f32,
+    pub bytes_per_second: f32,
+}
+
+/// Moving average of the rates between successive samples, like [`RateTracker`]
+pub struct FsRateTracker {
+    last_usage: FsUsage,
+    last_sample: Duration,
+    inodes_per_second: MovingAverage,
+    bytes_per_second: MovingAverage,
+}
+
+impl FsRateTracker {
+    /// Start from usage sampled at a given time since boot
+    pub fn new(usage: FsUsage, sampled_at: Duration) -> Self {
+        Self {
+            last_usage: usage,
+            last_sample: sampled_at,
+            inodes_per_second: MovingAverage::new(RateTracker::DEFAULT_WINDOW),
+            bytes_per_second: MovingAverage::new(RateTracker::DEFAULT_WINDOW),
+        }
+    }
+
+    pub fn update(&mut self, usage: FsUsage, sampled_at: Duration) -> Option<FsRate> {
+        if usage.fsid != self.last_usage.fsid {
+            *self = Self::new(usage, sampled_at);
+            return None;
+        }
+        let elapsed = sampled_at.saturating_sub(self.last_sample);
+        if !elapsed.is_zero() {
+            // Counters are too large for f32 to tell small differences: subtract them first
+            let per_second =
+                |a: u64, b: u64| (i128::from(a) - i128::from(b)) as f32 / elapsed.as_secs_f32();
+            self.inodes_per_second.update(
+                per_second(self.last_usage.used_inodes, usage.used_inodes),
+                elapsed,
+            );
+            self.bytes_per_second.update(
+                per_second(usage.free_bytes, self.last_usage.free_bytes),
+                elapsed,
+            );
+            self.last_usage = usage;
+            self.last_sample = sampled_at;
+        }
+        Some(FsRate {
+            inodes_per_second: self.inodes_per_second.value()?,
+            bytes_per_second: self.bytes_per_second.value()?,
+        })
+    }
+}
@@ -0,0 +1,20 @@
+//! Progress estimation for long-running rm processes, from what Linux exposes in procfs
+//!
+//! The `progressrm` binary is a frontend to this library.
+
+pub mod backtest;
+pub mod blockdev;
+pub mod clock;
+pub mod cmdline;
+pub mod diagnostics;
+pub mod error;
+pub mod filesystem;
+pub mod model;
+pub mod openfiles;
+pub mod path;
+pub mod prescan;
+pub mod procfs;
+pub mod progress;
+pub mod root;
+pub mod trace;
+pub mod xargs;
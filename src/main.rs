@@ -1,214 +1,1202 @@
+mod json;
+
+use progressrm::{
+    backtest::{self, Estimator},
+    blockdev::{BlockDevice, DeviceLoad, DeviceTracker},
+    clock::{Clock, SystemClock},
+    diagnostics::{Diagnostics, Snapshot},
+    error::Error,
+    filesystem::{FsRate, FsRateTracker, FsUsage},
+    model::{CommandModel, Find},
+    openfiles::{self, FileProgress, ThroughputTracker},
+    path::display_path,
+    prescan::{Prescan, WeightedEstimate},
+    procfs::{PidIterator, ProcSource, ProcessMatch, Procfs, parent_pid},
+    progress::{Estimate, Progress, RateTracker, Sampler},
+    trace::{Frame, LiveFilesystem, TraceWriter, read_trace},
+    xargs::{XargsJob, is_xargs},
+};
 use std::{
     collections::HashMap,
-    fs,
-    io::Read,
-    path::{Component, Path, PathBuf},
-    time::Duration,
+    ffi::OsString,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
-// Copy pasted from std so we don't have to rely on unstable feature:
-// https://github.com/rust-lang/rust/issues/134694
-/// An error returned from [`Path::normalize_lexically`] if a `..` parent reference
-/// would escape the path.
-#[derive(Debug, PartialEq)]
-#[non_exhaustive]
-pub struct NormalizeError;
-/// Normalize a path, including `..` without traversing the filesystem.
-///
-/// Returns an error if normalization would leave leading `..` components.
-///
-/// <div class="warning">
-///
-/// This function always resolves `..` to the "lexical" parent.
-/// That is "a/b/../c" will always resolve to `a/c` which can change the meaning of the path.
-/// In particular, `a/c` and `a/b/../c` are distinct on many systems because `b` may be a symbolic link, so its parent isn’t `a`.
-///
-/// </div>
-///
-/// [`path::absolute`](absolute) is an alternative that preserves `..`.
-/// Or [`Path::canonicalize`] can be used to resolve any `..` by querying the filesystem.
-pub fn normalize_lexically(p: &Path) -> Result<PathBuf, NormalizeError> {
-    let mut lexical = PathBuf::new();
-    let mut iter = p.components().peekable();
-
-    // Find the root, if any, and add it to the lexical path.
-    // Here we treat the Windows path "C:\" as a single "root" even though
-    // `components` splits it into two: (Prefix, RootDir).
-    let root = match iter.peek() {
-        Some(Component::ParentDir) => return Err(NormalizeError),
-        Some(p @ Component::RootDir) | Some(p @ Component::CurDir) => {
-            lexical.push(p);
-            iter.next();
-            lexical.as_os_str().len()
-        }
-        Some(Component::Prefix(prefix)) => {
-            lexical.push(prefix.as_os_str());
-            iter.next();
-            if let Some(p @ Component::RootDir) = iter.peek() {
-                lexical.push(p);
-                iter.next();
+/// What we know of a process at one point in time
+struct Status {
+    progress: Progress,
+    estimate: Estimate,
+    /// With `--prescan`
+    weighted: Option<WeightedEstimate>,
+    /// Once the filesystem was sampled twice
+    fs_rate: Option<FsRate>,
+    device: Option<BlockDevice>,
+    /// Once the device was sampled twice
+    device_load: Option<DeviceLoad>,
+    diagnostics: Option<Diagnostics>,
+    /// Command and pid of the find or xargs running this rm on a batch of files
+    batch_of: Option<(&'static str, u32)>,
+}
+
+fn report(status: &Status) -> String {
+    let (progress, estimate) = (&status.progress, &status.estimate);
+    let weighted = status.weighted.as_ref();
+    let Progress {
+        pid,
+        id,
+        intra,
+        args,
+        estimator,
+        ..
+    } = *progress;
+    let remaining = estimate
+        .eta
+        .map_or_else(|| "unknown".to_string(), time_format_human);
+    let tree = match intra {
+        Some(intra) => format!(
+            ", ~{:.0}% of its tree since monitoring began",
+            intra * 100.0
+        ),
+        None => String::new(),
+    };
+    let recent = match estimate.recent_rate {
+        Some(rate) => format!(" (recent {:.1} args/h)", rate * 3600.0),
+        None => String::new(),
+    };
+    let location = match (&progress.host_cwd, progress.other_mount_namespace) {
+        (Some(host_cwd), true) => format!(
+            " (host: {}, in another mount namespace)",
+            display_path(host_cwd)
+        ),
+        (Some(host_cwd), false) => format!(" (host: {})", display_path(host_cwd)),
+        (None, _) => String::new(),
+    };
+    let batch = match status.batch_of {
+        Some((command, parent)) => format!(" (batch of {command} [{parent}])"),
+        None => String::new(),
+    };
+    let mut out = format!(
+        "[{pid}] {} in {}{location}{batch}\n\
+        \t{:0.1}% (arg {} / {args}{tree}) {:.1} args/h{recent} remaining {remaining} ({estimator})\n",
+        progress.command,
+        display_path(&progress.cwd),
+        progress.position() * 100.0 / args as f32,
+        (id + 1).min(args),
+        estimate.rate * 3600.0,
+    );
+    match weighted.map(|weighted| (weighted, weighted.remaining)) {
+        None => {}
+        Some((_, None)) => out += "\tweighing operands...\n",
+        Some((weighted, Some(remaining))) => {
+            let items = match weighted.items_interval {
+                Some((low, high)) => format!("~{} items (95%: {low} – {high})", remaining.items),
+                None => format!("{} items", remaining.items),
+            };
+            let eta = match (weighted.eta_interval, weighted.eta) {
+                (Some((low, high)), _) => {
+                    format!(
+                        "{} – {}",
+                        time_format_human(low).trim_start(),
+                        time_format_human(high).trim_start()
+                    )
+                }
+                (None, Some(eta)) => time_format_human(eta),
+                (None, None) => "unknown".to_string(),
+            };
+            out += &format!(
+                "\t{items} ({}) in {} args remaining, {} of items removed since weighed, remaining {eta}\n",
+                bytes_format_human(remaining.bytes),
+                args - id.min(args),
+                weighted
+                    .fraction
+                    .map_or_else(|| "?".to_string(), |f| format!("{:.1}%", f * 100.0)),
+            )
+        }
+    }
+    if let Some(rate) = status.fs_rate {
+        let sign = if rate.bytes_per_second < 0.0 { "-" } else { "" };
+        out += &format!(
+            "\tfilesystem: {:.0} inodes/s, {sign}{}/s freed (includes other writers)\n",
+            rate.inodes_per_second,
+            bytes_format_human(rate.bytes_per_second.abs() as u64),
+        );
+    }
+    if let Some(device) = &status.device {
+        let kind = match device.rotational {
+            Some(true) => " (rotational)",
+            Some(false) => " (non-rotational)",
+            None => "",
+        };
+        out += &format!("\tdevice {}{kind}", device.name);
+        if let Some(load) = status.device_load {
+            out += &format!(
+                ": {:.0}% util, queue {:.1} ({} in flight), {:.0} r/s, {:.0} w/s",
+                load.utilization * 100.0,
+                load.queue_depth,
+                load.in_flight,
+                load.read_iops,
+                load.write_iops,
+            );
+        }
+        out += "\n";
+    }
+    if let Some(diagnostics) = &status.diagnostics {
+        let wchan = match &diagnostics.wchan {
+            Some(wchan) => format!(" in {wchan}"),
+            None => String::new(),
+        };
+        let ionice = match diagnostics.io_class {
+            Some(class) => format!(", ionice {class}"),
+            None => String::new(),
+        };
+        let syscalls = match diagnostics.syscalls_per_second {
+            Some((reads, writes)) => format!(", syscalls {reads:.0} r/s {writes:.0} w/s"),
+            None => String::new(),
+        };
+        let blocked_on_io = diagnostics
+            .blocked_on_io
+            .map_or_else(|| "unknown".to_string(), |f| format!("{:.0}%", f * 100.0));
+        out += &format!(
+            "\tstate {}{wchan}, CPU {:.0}%, blocked on IO {blocked_on_io}{ionice}, nice {}{syscalls}\n",
+            diagnostics.state,
+            diagnostics.cpu * 100.0,
+            diagnostics.nice,
+        );
+        let explanation = diagnostics.explain();
+        if !explanation.is_empty() {
+            out += &format!("\t=> {explanation}\n");
+        }
+    }
+    if progress.current_unlinked {
+        out += "\tcurrently removing a directory whose entry is already unlinked\n";
+    }
+    out
+}
+
+fn report_job(job: &XargsJob) -> String {
+    let input = display_path(&job.input);
+    match (job.fraction(), job.size) {
+        (Some(fraction), Some(size)) => format!(
+            "[{}] xargs job: {:.1}% of {input} ({} / {}) remaining {}\n",
+            job.pid,
+            fraction * 100.0,
+            bytes_format_human(job.position),
+            bytes_format_human(size),
+            job.eta()
+                .map_or_else(|| "unknown".to_string(), time_format_human),
+        ),
+        _ => format!(
+            "[{}] xargs job reading from {input}: not a regular file, progress unknown\n",
+            job.pid
+        ),
+    }
+}
+
+fn report_files(progress: &FileProgress, rate: Option<f32>) -> String {
+    let mut out = format!(
+        "[{}] {} in {}\n",
+        progress.pid,
+        progress.command,
+        display_path(&progress.cwd)
+    );
+    if progress.files.is_empty() {
+        out += "\tno regular file open\n";
+        return out;
+    }
+    let (_, size) = progress.total();
+    out += &format!(
+        "\t{} of {}, {}/s{}, remaining {}\n",
+        progress
+            .fraction()
+            .map_or_else(|| "?".to_string(), |f| format!("{:.1}%", f * 100.0)),
+        bytes_format_human(size),
+        bytes_format_human(rate.unwrap_or_else(|| progress.average_rate()) as u64),
+        if rate.is_some() { " recently" } else { "" },
+        openfiles::eta(progress, rate).map_or_else(|| "unknown".to_string(), time_format_human),
+    );
+    for file in &progress.files {
+        let position = match file.fraction() {
+            Some(fraction) if file.position < file.size => format!(
+                "{:.1}% ({} / {})",
+                fraction * 100.0,
+                bytes_format_human(file.position),
+                bytes_format_human(file.size)
+            ),
+            _ => bytes_format_human(file.size),
+        };
+        out += &format!(
+            "\tfd {} {} {position} {}\n",
+            file.fd,
+            file.access,
+            display_path(&file.path)
+        );
+    }
+    out
+}
+
+fn report_error(pid: u32, error: &Error) -> String {
+    format!("[{pid}] no progress info ({error})\n")
+}
+
+/// Version of the JSON record schema, to be bumped on incompatible changes
+const JSON_SCHEMA_VERSION: u64 = 1;
+
+fn report_json(status: &Status, now: SystemTime) -> String {
+    use json::Value;
+    let (progress, estimate) = (&status.progress, &status.estimate);
+    let weighted = status.weighted.as_ref();
+    let remaining = weighted.and_then(|weighted| weighted.remaining);
+    let diagnostics = status.diagnostics.as_ref();
+    json::object(&[
+        ("schema_version", Value::Int(JSON_SCHEMA_VERSION)),
+        ("timestamp", Value::String(json::timestamp(now))),
+        ("pid", Value::Int(progress.pid.into())),
+        ("command", Value::String(progress.command.clone())),
+        (
+            "batch_of",
+            status
+                .batch_of
+                .map_or(Value::Null, |(_, parent)| Value::Int(parent.into())),
+        ),
+        ("cwd", Value::String(display_path(&progress.cwd))),
+        (
+            "host_cwd",
+            progress.host_cwd.as_deref().map(display_path).into(),
+        ),
+        (
+            "other_mount_namespace",
+            Value::Bool(progress.other_mount_namespace),
+        ),
+        ("operands", Value::Int(progress.args as u64)),
+        ("completed", Value::Int(progress.id as u64)),
+        ("position", Value::Float(progress.position().into())),
+        (
+            "percent",
+            Value::Float((progress.position() * 100.0 / progress.args as f32).into()),
+        ),
+        ("rate", Value::Float(estimate.rate.into())),
+        ("recent_rate", estimate.recent_rate.map(f64::from).into()),
+        (
+            "elapsed_seconds",
+            Value::Float(progress.time_since_start.as_secs_f64()),
+        ),
+        (
+            "eta_seconds",
+            estimate.eta.map(|eta| eta.as_secs_f64()).into(),
+        ),
+        (
+            "eta_timestamp",
+            estimate
+                .eta
+                .and_then(|eta| now.checked_add(eta))
+                .map(json::timestamp)
+                .into(),
+        ),
+        ("estimator", Value::String(progress.estimator.to_string())),
+        ("current_unlinked", Value::Bool(progress.current_unlinked)),
+        (
+            "items_remaining",
+            remaining.map_or(Value::Null, |r| Value::Int(r.items)),
+        ),
+        (
+            "bytes_remaining",
+            remaining.map_or(Value::Null, |r| Value::Int(r.bytes)),
+        ),
+        (
+            "items_remaining_low",
+            weighted
+                .and_then(|weighted| weighted.items_interval)
+                .map_or(Value::Null, |(low, _)| Value::Int(low)),
+        ),
+        (
+            "items_remaining_high",
+            weighted
+                .and_then(|weighted| weighted.items_interval)
+                .map_or(Value::Null, |(_, high)| Value::Int(high)),
+        ),
+        (
+            "items_percent",
+            weighted
+                .and_then(|weighted| weighted.fraction)
+                .map(|f| f64::from(f) * 100.0)
+                .into(),
+        ),
+        (
+            "items_eta_seconds",
+            weighted
+                .and_then(|weighted| weighted.eta)
+                .map(|eta| eta.as_secs_f64())
+                .into(),
+        ),
+        (
+            "items_eta_low_seconds",
+            weighted
+                .and_then(|weighted| weighted.eta_interval)
+                .map(|(low, _)| low.as_secs_f64())
+                .into(),
+        ),
+        (
+            "items_eta_high_seconds",
+            weighted
+                .and_then(|weighted| weighted.eta_interval)
+                .map(|(_, high)| high.as_secs_f64())
+                .into(),
+        ),
+        (
+            "fs_inodes_freed_per_second",
+            status
+                .fs_rate
+                .map(|rate| f64::from(rate.inodes_per_second))
+                .into(),
+        ),
+        (
+            "fs_bytes_freed_per_second",
+            status
+                .fs_rate
+                .map(|rate| f64::from(rate.bytes_per_second))
+                .into(),
+        ),
+        (
+            "device",
+            status
+                .device
+                .as_ref()
+                .map(|device| device.name.clone())
+                .into(),
+        ),
+        (
+            "device_rotational",
+            status
+                .device
+                .as_ref()
+                .and_then(|device| device.rotational)
+                .map_or(Value::Null, Value::Bool),
+        ),
+        (
+            "device_utilization_percent",
+            status
+                .device_load
+                .map(|load| f64::from(load.utilization) * 100.0)
+                .into(),
+        ),
+        (
+            "device_queue_depth",
+            status
+                .device_load
+                .map(|load| f64::from(load.queue_depth))
+                .into(),
+        ),
+        (
+            "device_read_iops",
+            status
+                .device_load
+                .map(|load| f64::from(load.read_iops))
+                .into(),
+        ),
+        (
+            "device_write_iops",
+            status
+                .device_load
+                .map(|load| f64::from(load.write_iops))
+                .into(),
+        ),
+        (
+            "state",
+            diagnostics
+                .map(|diagnostics| diagnostics.state.to_string())
+                .into(),
+        ),
+        (
+            "wchan",
+            diagnostics
+                .and_then(|diagnostics| diagnostics.wchan.clone())
+                .into(),
+        ),
+        (
+            "nice",
+            diagnostics
+                .map(|diagnostics| diagnostics.nice as f64)
+                .into(),
+        ),
+        (
+            "ionice",
+            diagnostics
+                .and_then(|diagnostics| diagnostics.io_class)
+                .map(|class| class.to_string())
+                .into(),
+        ),
+        (
+            "cpu_percent",
+            diagnostics
+                .map(|diagnostics| f64::from(diagnostics.cpu) * 100.0)
+                .into(),
+        ),
+        (
+            "io_wait_percent",
+            diagnostics
+                .and_then(|diagnostics| diagnostics.blocked_on_io)
+                .map(|f| f64::from(f) * 100.0)
+                .into(),
+        ),
+        (
+            "read_syscalls_per_second",
+            diagnostics
+                .and_then(|diagnostics| diagnostics.syscalls_per_second)
+                .map(|(reads, _)| f64::from(reads))
+                .into(),
+        ),
+        (
+            "write_syscalls_per_second",
+            diagnostics
+                .and_then(|diagnostics| diagnostics.syscalls_per_second)
+                .map(|(_, writes)| f64::from(writes))
+                .into(),
+        ),
+        (
+            "read_bytes_per_second",
+            diagnostics
+                .and_then(|diagnostics| diagnostics.bytes_per_second)
+                .map(|(reads, _)| f64::from(reads))
+                .into(),
+        ),
+        (
+            "write_bytes_per_second",
+            diagnostics
+                .and_then(|diagnostics| diagnostics.bytes_per_second)
+                .map(|(_, writes)| f64::from(writes))
+                .into(),
+        ),
+        (
+            "diagnosis",
+            diagnostics.map(|diagnostics| diagnostics.explain()).into(),
+        ),
+    ])
+}
+
+fn report_job_json(job: &XargsJob, now: SystemTime) -> String {
+    use json::Value;
+    let eta = job.eta();
+    json::object(&[
+        ("schema_version", Value::Int(JSON_SCHEMA_VERSION)),
+        ("timestamp", Value::String(json::timestamp(now))),
+        ("pid", Value::Int(job.pid.into())),
+        ("command", Value::String("xargs".to_string())),
+        ("input", Value::String(display_path(&job.input))),
+        ("input_position", Value::Int(job.position)),
+        ("input_size", job.size.map_or(Value::Null, Value::Int)),
+        (
+            "percent",
+            job.fraction().map(|f| f64::from(f) * 100.0).into(),
+        ),
+        (
+            "elapsed_seconds",
+            Value::Float(job.time_since_start.as_secs_f64()),
+        ),
+        ("eta_seconds", eta.map(|eta| eta.as_secs_f64()).into()),
+        (
+            "eta_timestamp",
+            eta.and_then(|eta| now.checked_add(eta))
+                .map(json::timestamp)
+                .into(),
+        ),
+    ])
+}
+
+/// A record for the process, then one per file
+fn report_files_json(progress: &FileProgress, rate: Option<f32>, now: SystemTime) -> Vec<String> {
+    use json::Value;
+    let (position, size) = progress.total();
+    let eta = openfiles::eta(progress, rate);
+    let mut records = vec![json::object(&[
+        ("schema_version", Value::Int(JSON_SCHEMA_VERSION)),
+        ("timestamp", Value::String(json::timestamp(now))),
+        ("pid", Value::Int(progress.pid.into())),
+        ("command", Value::String(progress.command.clone())),
+        ("cwd", Value::String(display_path(&progress.cwd))),
+        ("files", Value::Int(progress.files.len() as u64)),
+        ("position", Value::Int(position)),
+        ("size", Value::Int(size)),
+        (
+            "percent",
+            progress.fraction().map(|f| f64::from(f) * 100.0).into(),
+        ),
+        (
+            "bytes_per_second",
+            Value::Float(rate.unwrap_or_else(|| progress.average_rate()).into()),
+        ),
+        (
+            "elapsed_seconds",
+            Value::Float(progress.time_since_start.as_secs_f64()),
+        ),
+        ("eta_seconds", eta.map(|eta| eta.as_secs_f64()).into()),
+        (
+            "eta_timestamp",
+            eta.and_then(|eta| now.checked_add(eta))
+                .map(json::timestamp)
+                .into(),
+        ),
+    ])];
+    records.extend(progress.files.iter().map(|file| {
+        json::object(&[
+            ("schema_version", Value::Int(JSON_SCHEMA_VERSION)),
+            ("timestamp", Value::String(json::timestamp(now))),
+            ("pid", Value::Int(progress.pid.into())),
+            ("fd", Value::Int(file.fd.into())),
+            ("path", Value::String(display_path(&file.path))),
+            ("access", Value::String(file.access.to_string())),
+            ("position", Value::Int(file.position)),
+            ("size", Value::Int(file.size)),
+            (
+                "percent",
+                file.fraction().map(|f| f64::from(f) * 100.0).into(),
+            ),
+        ])
+    }));
+    records
+}
+
+fn report_error_json(pid: u32, error: &Error, now: SystemTime) -> String {
+    use json::Value;
+    json::object(&[
+        ("schema_version", Value::Int(JSON_SCHEMA_VERSION)),
+        ("timestamp", Value::String(json::timestamp(now))),
+        ("pid", Value::Int(pid.into())),
+        ("error", Value::String(error.to_string())),
+    ])
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    /// A JSON array of records
+    Json,
+    /// One JSON record per line
+    Ndjson,
+}
+
+/// Estimates progress over successive frames
+struct Monitor {
+    format: Format,
+    rates: HashMap<u32, RateTracker>,
+    /// Weigh operands in the background
+    prescan: bool,
+    /// Weigh only that many operands picked at random
+    sample_size: Option<usize>,
+    /// Wait for weighing to complete, for a single snapshot
+    wait_prescan: bool,
+    prescans: HashMap<u32, Prescan>,
+    fs_rates: HashMap<u32, FsRateTracker>,
+    devices: HashMap<u32, DeviceTracker>,
+    snapshots: HashMap<u32, Snapshot>,
+    /// Report on open files instead of operands
+    files: bool,
+    throughputs: HashMap<u32, ThroughputTracker>,
+}
+
+impl Monitor {
+    fn new(format: Format) -> Self {
+        Self {
+            format,
+            rates: HashMap::new(),
+            prescan: false,
+            sample_size: None,
+            wait_prescan: false,
+            prescans: HashMap::new(),
+            fs_rates: HashMap::new(),
+            devices: HashMap::new(),
+            snapshots: HashMap::new(),
+            files: false,
+            throughputs: HashMap::new(),
+        }
+    }
+
+    /// One report per process, in the monitor's format
+    fn frame(
+        &mut self,
+        sampler: &Sampler,
+        process_match: &[ProcessMatch],
+    ) -> Result<Vec<String>, Error> {
+        if self.files {
+            return self.files_frame(sampler, process_match);
+        }
+        let now = sampler.clock.wall();
+        let mut records = Vec::new();
+        let mut seen = Vec::new();
+        let mut samples: Vec<(u32, Option<u32>, Result<Progress, Error>)> =
+            PidIterator::new(sampler.source, &sampler.models, process_match)?
+                .map(|pid| {
+                    let parent = parent_pid(sampler.source, pid).ok();
+                    (pid, parent, sampler.sample(pid))
+                })
+                // Plenty of find processes only search, and of chmod and the like don't recurse
+                .filter(|(_, _, sample)| {
+                    !matches!(sample, Err(Error::NotDeleting | Error::NotRecursive))
+                })
+                .collect();
+        let finds: Vec<u32> = samples
+            .iter()
+            .filter_map(|(pid, _, sample)| {
+                sample
+                    .as_ref()
+                    .is_ok_and(|progress| progress.command == "find")
+                    .then_some(*pid)
+            })
+            .collect();
+        // The xargs running rm batches, to report on the whole job
+        let mut jobs: HashMap<u32, Result<XargsJob, Error>> = HashMap::new();
+        for &(_, parent, _) in &samples {
+            if let Some(parent) = parent
+                && !finds.contains(&parent)
+                && !jobs.contains_key(&parent)
+                && is_xargs(sampler.source, parent)
+            {
+                let job = XargsJob::sample(sampler.source, sampler.clock, parent);
+                jobs.insert(parent, job);
             }
-            lexical.as_os_str().len()
         }
-        None => return Ok(PathBuf::new()),
-        Some(Component::Normal(_)) => 0,
-    };
+        // Reported as jobs, if they were matched too
+        samples.retain(|(pid, _, _)| !jobs.contains_key(pid));
+        let batch_of = |parent: Option<u32>| {
+            let parent = parent?;
+            if finds.contains(&parent) {
+                Some(("find", parent))
+            } else {
+                jobs.contains_key(&parent).then_some(("xargs", parent))
+            }
+        };
+        // rm batches right after their find or xargs job
+        samples.sort_by_key(|&(pid, parent, _)| match batch_of(parent) {
+            Some((_, parent)) => (parent, pid),
+            None => (pid, 0),
+        });
+        let batches: Vec<_> = samples
+            .into_iter()
+            .map(|(pid, parent, sample)| (pid, batch_of(parent), sample))
+            .collect();
+        for (pid, batch_of, sample) in batches {
+            seen.push(pid);
+            if let Some((_, parent)) = batch_of
+                && let Some(job) = jobs.remove(&parent)
+            {
+                records.push(match (self.format, job) {
+                    (Format::Text, Ok(job)) => report_job(&job),
+                    (Format::Json | Format::Ndjson, Ok(job)) => report_job_json(&job, now),
+                    (Format::Text, Err(e)) => report_error(parent, &e),
+                    (Format::Json | Format::Ndjson, Err(e)) => report_error_json(parent, &e, now),
+                });
+            }
+            let progress = match sample {
+                Ok(progress) => progress,
+                Err(e) => {
+                    records.push(match self.format {
+                        Format::Text => report_error(pid, &e),
+                        Format::Json | Format::Ndjson => report_error_json(pid, &e, now),
+                    });
+                    continue;
+                }
+            };
+            let recent_rate = match self.rates.get_mut(&pid) {
+                Some(tracker) => tracker.update(&progress),
+                None => {
+                    self.rates.insert(pid, RateTracker::new(&progress));
+                    None
+                }
+            };
+            let estimate = Estimate::new(&progress, recent_rate);
+            // Operands are only available live
+            let weighted = (self.prescan && sampler.probe_filesystem).then(|| {
+                let prescan = self.prescans.entry(pid).or_insert_with(|| {
+                    Prescan::start(progress.operands.clone(), progress.id, self.sample_size)
+                });
+                if self.wait_prescan {
+                    prescan.wait();
+                }
+                prescan.estimate(&progress)
+            });
+            // Where the current operand is, or the last one once all are done
+            let operand = progress
+                .operands
+                .get(progress.id)
+                .or(progress.operands.last())
+                .filter(|_| sampler.probe_filesystem);
+            let usage = operand.and_then(|operand| FsUsage::of(operand).ok());
+            let fs_rate = usage.and_then(|usage| match self.fs_rates.get_mut(&pid) {
+                Some(tracker) => tracker.update(usage, progress.sampled_at),
+                None => {
+                    self.fs_rates
+                        .insert(pid, FsRateTracker::new(usage, progress.sampled_at));
+                    None
+                }
+            });
+            let device = operand.and_then(|operand| BlockDevice::of(operand).ok());
+            let device_load = device.as_ref().and_then(|device| {
+                let tracker = self
+                    .devices
+                    .entry(pid)
+                    .and_modify(|tracker| {
+                        // rm moved on to another device
+                        if tracker.device.name != device.name {
+                            *tracker = DeviceTracker::new(device.clone());
+                        }
+                    })
+                    .or_insert_with(|| DeviceTracker::new(device.clone()));
+                tracker.update(progress.sampled_at).ok().flatten()
+            });
+            let diagnostics =
+                Snapshot::take(sampler.source, sampler.clock, pid)
+                    .ok()
+                    .map(|snapshot| {
+                        let diagnostics = Diagnostics::new(&snapshot, self.snapshots.get(&pid));
+                        self.snapshots.insert(pid, snapshot);
+                        diagnostics
+                    });
+            let status = Status {
+                progress,
+                estimate,
+                weighted,
+                fs_rate,
+                device,
+                device_load,
+                diagnostics,
+                batch_of,
+            };
+            records.push(match self.format {
+                Format::Text => report(&status),
+                Format::Json | Format::Ndjson => report_json(&status, now),
+            });
+        }
+        self.rates.retain(|pid, _| seen.contains(pid));
+        self.prescans.retain(|pid, _| seen.contains(pid));
+        self.fs_rates.retain(|pid, _| seen.contains(pid));
+        self.devices.retain(|pid, _| seen.contains(pid));
+        self.snapshots.retain(|pid, _| seen.contains(pid));
+        Ok(records)
+    }
 
-    for component in iter {
-        match component {
-            Component::RootDir => unreachable!(),
-            Component::Prefix(_) => return Err(NormalizeError),
-            Component::CurDir => continue,
-            Component::ParentDir => {
-                // It's an error if ParentDir causes us to go above the "root".
-                if lexical.as_os_str().len() == root {
-                    return Err(NormalizeError);
-                } else {
-                    lexical.pop();
+    /// Reports on the open files of each process
+    fn files_frame(
+        &mut self,
+        sampler: &Sampler,
+        process_match: &[ProcessMatch],
+    ) -> Result<Vec<String>, Error> {
+        let now = sampler.clock.wall();
+        let mut records = Vec::new();
+        let mut seen = Vec::new();
+        for pid in PidIterator::new(sampler.source, &sampler.models, process_match)? {
+            seen.push(pid);
+            let progress = match FileProgress::sample(sampler.source, sampler.clock, pid) {
+                Ok(progress) => progress,
+                Err(e) => {
+                    records.push(match self.format {
+                        Format::Text => report_error(pid, &e),
+                        Format::Json | Format::Ndjson => report_error_json(pid, &e, now),
+                    });
+                    continue;
+                }
+            };
+            let rate = match self.throughputs.get_mut(&pid) {
+                Some(tracker) => tracker.update(&progress),
+                None => {
+                    self.throughputs
+                        .insert(pid, ThroughputTracker::new(&progress));
+                    None
+                }
+            };
+            match self.format {
+                Format::Text => records.push(report_files(&progress, rate)),
+                Format::Json | Format::Ndjson => {
+                    records.extend(report_files_json(&progress, rate, now))
                 }
             }
-            Component::Normal(path) => lexical.push(path),
-        }
-    }
-    Ok(lexical)
-}
-
-struct PidIterator {
-    pids: Box<dyn Iterator<Item = u32>>,
-}
-impl PidIterator {
-    pub fn new(process_match: &'static str) -> Result<Self, String> {
-        Ok(Self {
-            pids: Box::new(
-                fs::read_dir("/proc")
-                    .map_err(|e| format!("opening /proc: {e}"))?
-                    .filter_map(|res| res.ok()) // discard errors for individual files
-                    .map(|f| f.file_name().into_string()) // keep only basename from path, and only Strings
-                    .filter_map(|res| res.ok()) // valid utf-8 only
-                    .filter(|f| f.as_bytes().iter().all(|c| c.is_ascii_digit())) // only pids (digit-only strings)
-                    .filter_map(|f| f.parse::<u32>().ok()) // as integers
-                    .filter(move |pid| {
-                        if let Ok(path) = fs::read_link(format!("/proc/{pid}/exe"))
-                            && let Some(s) = path.to_str()
-                        {
-                            return s.contains(process_match);
-                        }
-                        false
-                    }), // only process which exe matches pattern
-            ),
-        })
+        }
+        self.throughputs.retain(|pid, _| seen.contains(pid));
+        Ok(records)
     }
 }
-impl Iterator for PidIterator {
-    type Item = u32;
-    fn next(&mut self) -> Option<Self::Item> {
-        self.pids.next()
+
+/// Print the reports of a frame
+fn print_records(format: Format, records: &[String]) {
+    match format {
+        Format::Text => print!("{}", records.concat()),
+        Format::Json => println!("[{}]", records.join(",")),
+        Format::Ndjson => records.iter().for_each(|record| println!("{record}")),
     }
 }
 
-pub struct FdIterator {
-    fds: fs::ReadDir,
+enum Command {
+    /// Report on live processes
+    Monitor,
+    /// Save procfs snapshots to a trace file
+    Record(PathBuf),
+    /// Report on the processes of a trace file
+    Replay(PathBuf),
+    /// Compare ETA estimators on a trace file
+    Backtest(PathBuf),
+}
+
+struct Options {
+    command: Command,
+    /// Refresh interval, when watching
+    watch: Option<Duration>,
+    /// Interval between snapshots, when recording
+    interval: Duration,
+    /// Weigh operands to estimate progress in items
+    prescan: bool,
+    /// Weigh only a random sample of the operands
+    sample_size: Option<usize>,
+    /// Also monitor `find -delete` and `find -exec rm`
+    find: bool,
+    /// Report on open files instead of operands
+    files: bool,
+    process_match: Vec<ProcessMatch>,
+    format: Format,
+    /// Where procfs is mounted
+    proc_root: PathBuf,
 }
 
-impl FdIterator {
-    pub fn new(pid: u32) -> Result<Self, String> {
-        Ok(FdIterator {
-            fds: fs::read_dir(format!("/proc/{pid}/fd"))
-                .map_err(|e| format!("cannot open fd dir: {e}"))?,
-        })
+impl Options {
+    const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+    fn parse(args: impl Iterator<Item = OsString>) -> Result<Self, String> {
+        let mut options = Options {
+            command: Command::Monitor,
+            watch: None,
+            interval: Self::DEFAULT_WATCH_INTERVAL,
+            prescan: false,
+            sample_size: None,
+            find: false,
+            files: false,
+            process_match: Vec::new(),
+            format: Format::Text,
+            proc_root: PathBuf::from("/proc"),
+        };
+        let mut args = args.peekable();
+        let subcommand = match args.peek().and_then(|arg| arg.to_str()) {
+            Some("record") => Some(Command::Record as fn(PathBuf) -> Command),
+            Some("replay") => Some(Command::Replay as fn(PathBuf) -> Command),
+            Some("backtest") => Some(Command::Backtest as fn(PathBuf) -> Command),
+            _ => None,
+        };
+        if let Some(subcommand) = subcommand {
+            args.next();
+            let file = args
+                .next()
+                .ok_or_else(|| "missing trace file".to_string())?;
+            options.command = subcommand(PathBuf::from(file));
+        }
+        while let Some(arg) = args.next() {
+            let arg = arg
+                .into_string()
+                .map_err(|arg| format!("invalid argument: {}", arg.to_string_lossy()))?;
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next()?.into_string().ok())
+                    .ok_or_else(|| format!("missing value for {name}"))
+            };
+            match name {
+                "--watch" => {
+                    // Interval is optional
+                    let interval = match &inline_value {
+                        Some(interval) => Some(parse_interval(interval)?),
+                        None => {
+                            let interval = args
+                                .peek()
+                                .and_then(|next| parse_interval(next.to_str()?).ok());
+                            if interval.is_some() {
+                                args.next();
+                            }
+                            interval
+                        }
+                    };
+                    options.watch = Some(interval.unwrap_or(Self::DEFAULT_WATCH_INTERVAL));
+                }
+                "--prescan" => options.prescan = true,
+                "--sample" => {
+                    let size = value()?;
+                    options.prescan = true;
+                    options.sample_size = Some(
+                        size.parse()
+                            .ok()
+                            .filter(|&size| size > 0)
+                            .ok_or_else(|| format!("invalid sample size: {size}"))?,
+                    );
+                }
+                "--find" => options.find = true,
+                "--files" => options.files = true,
+                "--interval" => options.interval = parse_interval(&value()?)?,
+                "--format" => {
+                    options.format = match value()?.as_str() {
+                        "text" => Format::Text,
+                        "json" => Format::Json,
+                        "ndjson" => Format::Ndjson,
+                        format => return Err(format!("unknown format: {format}")),
+                    }
+                }
+                "--proc-root" => options.proc_root = PathBuf::from(value()?),
+                "--name" => options
+                    .process_match
+                    .push(ProcessMatch::Name(non_empty(name, value()?)?)),
+                "--exe" => options
+                    .process_match
+                    .push(ProcessMatch::Exe(non_empty(name, value()?)?)),
+                "--comm" => options
+                    .process_match
+                    .push(ProcessMatch::Comm(non_empty(name, value()?)?)),
+                "--pid" => {
+                    let pid = value()?;
+                    options.process_match.push(ProcessMatch::Pid(
+                        pid.parse().map_err(|e| format!("invalid pid {pid}: {e}"))?,
+                    ));
+                }
+                _ => return Err(format!("unknown argument: {arg}")),
+            }
+        }
+        if options.watch.is_some() && options.format == Format::Json {
+            return Err("json is a single snapshot, use ndjson to watch".to_string());
+        }
+        if matches!(options.command, Command::Backtest(_)) && options.format != Format::Text {
+            return Err("backtest only has text output".to_string());
+        }
+        // A trace only has the processes that were recorded
+        let replaying = matches!(options.command, Command::Replay(_) | Command::Backtest(_));
+        if options.process_match.is_empty() && !replaying {
+            if options.files {
+                options.process_match.extend(
+                    FILE_TOOLS
+                        .iter()
+                        .map(|name| ProcessMatch::Name(name.to_string())),
+                );
+            } else {
+                options.process_match.push(ProcessMatch::Supported);
+            }
+        }
+        if options.find {
+            options
+                .process_match
+                .push(ProcessMatch::Model(Find.name().to_string()));
+        }
+        Ok(options)
     }
 }
-impl Iterator for FdIterator {
-    type Item = PathBuf;
-    fn next(&mut self) -> Option<Self::Item> {
-        for fd in (&mut self.fds).filter_map(|res| res.ok()) {
-            if let Ok(link) = fs::read_link(fd.path())
-                && let Some(s) = link.to_str()
-                && s.starts_with("/")
-            {
-                return Some(link);
+
+/// Tools monitored by default with `--files`
+const FILE_TOOLS: &[&str] = &[
+    "cp",
+    "mv",
+    "dd",
+    "cat",
+    "tar",
+    "rsync",
+    "gzip",
+    "gunzip",
+    "bzip2",
+    "xz",
+    "zstd",
+    "md5sum",
+    "sha1sum",
+    "sha256sum",
+    "sha512sum",
+    "b2sum",
+    "shred",
+];
+
+/// Parse an interval in seconds, possibly fractional
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    s.parse::<f32>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f32(secs).ok())
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| format!("invalid interval: {s}"))
+}
+
+/// A pattern that would match everything, or nothing, is most likely a mistake
+fn non_empty(name: &str, value: String) -> Result<String, String> {
+    if value.is_empty() {
+        return Err(format!("empty value for {name}"));
+    }
+    Ok(value)
+}
+
+/// Capture snapshots until no process matches
+fn record(
+    source: &dyn ProcSource,
+    clock: &dyn Clock,
+    options: &Options,
+    file: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file =
+        File::create(file).map_err(|e| format!("cannot create {}: {e}", display_path(file)))?;
+    let mut writer = TraceWriter::new(BufWriter::new(file))?;
+    let live_filesystem = LiveFilesystem::new(source);
+    let mut sampler = Sampler::new(&live_filesystem, clock);
+    sampler.probe_filesystem = false;
+    sampler.record_filesystem = true;
+    let mut frames = 0;
+    loop {
+        let mut frame = Frame::capture(source, clock, &sampler.models, &options.process_match)?;
+        frame.record_filesystem(&live_filesystem, &sampler);
+        if frame.processes.processes.is_empty() {
+            // The empty frame tells when the processes exited
+            if frames > 0 {
+                writer.write_frame(&frame)?;
+            }
+            break;
+        }
+        writer.write_frame(&frame)?;
+        frames += 1;
+        eprint!(
+            "\rrecorded {frames} frames, {} processes",
+            frame.processes.processes.len()
+        );
+        std::thread::sleep(options.interval);
+    }
+    if frames == 0 {
+        eprintln!("no matching process");
+    } else {
+        eprintln!();
+    }
+    Ok(())
+}
+
+/// Estimate progress from the frames of a trace, as if they were live
+fn replay(options: &Options, file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::open(file).map_err(|e| format!("cannot open {}: {e}", display_path(file)))?;
+    let frames = read_trace(BufReader::new(file))?;
+    let Some(first) = frames.first() else {
+        return Ok(());
+    };
+    let mut monitor = Monitor::new(options.format);
+    monitor.files = options.files;
+    let mut all_records = Vec::new();
+    // Kept across frames, for the directory listings as first seen
+    let mut sampler = Sampler::new(&first.processes, &first.clock);
+    // The filesystem has changed since
+    sampler.probe_filesystem = false;
+    for frame in &frames {
+        sampler.source = &frame.processes;
+        sampler.clock = &frame.clock;
+        let records = monitor.frame(&sampler, &options.process_match)?;
+        match options.format {
+            Format::Text => {
+                println!("{}", json::timestamp(frame.clock.wall));
+                print_records(options.format, &records);
             }
+            Format::Json => all_records.extend(records),
+            Format::Ndjson => print_records(options.format, &records),
         }
-        None
     }
+    if options.format == Format::Json {
+        print_records(options.format, &all_records);
+    }
+    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    for pid in PidIterator::new("/usr/bin/rm")? {
-        let cwd: PathBuf = fs::read_link(format!("/proc/{pid}/cwd"))?;
-        // Parse cmdline
-        let mut cmdline_file = fs::File::open(format!("/proc/{pid}/cmdline"))?;
-        let mut cmdline_content = String::new();
-        _ = cmdline_file.read_to_string(&mut cmdline_content)?;
-        let cmdline: Vec<PathBuf> = cmdline_content
-            .split_terminator('\0')
-            .skip(1) // Skip command name
-            .filter(|s| !s.starts_with("-")) // Normally this filter should not be effective after --; but this needs a stateful parser and we're still at PoC stage
-            .map(|s| normalize_lexically(&cwd.join(s)).expect("normalizable path"))
-            .collect();
-        let lookup_hash: HashMap<PathBuf, usize> = cmdline
-            .iter()
-            .cloned()
-            .enumerate()
-            .map(|(i, el)| (el, i))
-            .collect();
-        let time_since_start = process_time_since_start(pid)?;
-        let id = FdIterator::new(pid)?
-            .filter_map(|filename| {
-                let mut components = filename.components();
-                loop {
-                    let p = components.as_path().to_owned();
-                    if let Some(i) = lookup_hash.get(&p) {
-                        return Some(i);
-                    }
-                    if components.next_back().is_none() {
-                        break;
-                    }
-                }
-                None
-            })
-            .max()
-            .expect("No open matching open file");
-        let args_per_second = *id as f32 / time_since_start.as_secs_f32();
-        let remaining_args = cmdline.len() - id;
+/// Report how far off each estimator's ETA was over a trace
+fn backtest(file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::open(file).map_err(|e| format!("cannot open {}: {e}", display_path(file)))?;
+    let frames = read_trace(BufReader::new(file))?;
+    let completed = backtest::completion_times(&frames).len();
+    if completed == 0 {
+        return Err("no process exited during the trace".into());
+    }
+    let results = backtest::backtest(&frames, &Estimator::all());
+    let seconds =
+        |error: Option<f64>| error.map_or_else(|| "-".to_string(), |e| format!("{e:+.1}"));
+    println!(
+        "{completed} processes completed over {} frames, errors in seconds (predicted - real completion)",
+        frames.len()
+    );
+    println!(
+        "{:<20}{:>9}{:>10}{:>10}{:>10}",
+        "estimator", "samples", "with ETA", "MAE", "bias"
+    );
+    for result in &results {
         println!(
-            "[{pid}] rm in {}\n\
-            \t{:0.1}% ({id} / {} args) {:.1} args/h remaining {}",
-            cwd.to_string_lossy(),
-            *id as f32 * 100.0 / cmdline.len() as f32,
-            cmdline.len(),
-            args_per_second * 3600.0,
-            time_format_human(time_since_start.mul_f32(remaining_args as f32 / *id as f32)),
+            "{:<20}{:>9}{:>10}{:>10}{:>10}",
+            result.estimator.to_string(),
+            result.errors.len(),
+            result.with_eta(),
+            seconds(result.mean_absolute_error()).trim_start_matches('+'),
+            seconds(result.bias()),
         );
     }
+    println!("\nMAE by elapsed fraction of the process lifetime");
+    print!("{:<20}", "estimator");
+    for decile in 0..10 {
+        print!("{:>8}", format!("{}%", decile * 10));
+    }
+    println!();
+    for result in &results {
+        print!("{:<20}", result.estimator.to_string());
+        for error in result.mean_absolute_error_over_time() {
+            print!("{:>8}", seconds(error).trim_start_matches('+'));
+        }
+        println!();
+    }
     Ok(())
 }
 
-fn process_time_since_start(pid: u32) -> Result<Duration, String> {
-    let pid_stat_name = format!("/proc/{pid}/stat");
-    let pid_stat = fs::read_to_string(&pid_stat_name)
-        .map_err(|e| format!("Cannot read {pid_stat_name}: {e}"))?;
-    let start_time_after_boot = pid_stat
-        .split_ascii_whitespace()
-        .nth(21)
-        .ok_or_else(|| format!("No starttime in {pid_stat_name}"))?
-        .parse::<u64>()
-        .map_err(|e| format!("starttime parse error: {e}"))?;
-    let process_start_time_after_boot = start_time_after_boot / system_ticks_per_second()?;
-    let time_since_boot_secs = nix::time::clock_gettime(nix::time::ClockId::CLOCK_BOOTTIME)
-        .map_err(|e| format!("cannot get time: {e}"))?
-        .tv_sec() as u64;
-    Ok(Duration::from_secs(
-        time_since_boot_secs - process_start_time_after_boot,
-    ))
-}
-
-fn system_ticks_per_second() -> Result<u64, String> {
-    nix::unistd::sysconf(nix::unistd::SysconfVar::CLK_TCK)
-        .map_err(|e| format!("Cannot get system clock ticks: {e}"))?
-        .ok_or_else(|| "empty clock tick".to_string())?
-        .try_into()
-        .map_err(|e| format!("negative clock ticks: {e}"))
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = Options::parse(std::env::args_os().skip(1))?;
+    let source: &dyn ProcSource = &Procfs::new(&options.proc_root);
+    let clock: &dyn Clock = &SystemClock;
+    match &options.command {
+        Command::Monitor => {}
+        Command::Record(file) => return record(source, clock, &options, file),
+        Command::Replay(file) => return replay(&options, file),
+        Command::Backtest(file) => return backtest(file),
+    }
+    let sampler = Sampler::new(source, clock);
+    let mut monitor = Monitor::new(options.format);
+    monitor.prescan = options.prescan;
+    monitor.sample_size = options.sample_size;
+    monitor.wait_prescan = options.watch.is_none();
+    monitor.files = options.files;
+    let Some(interval) = options.watch else {
+        print_records(
+            options.format,
+            &monitor.frame(&sampler, &options.process_match)?,
+        );
+        return Ok(());
+    };
+    let mut previous_lines = 0;
+    loop {
+        let records = monitor.frame(&sampler, &options.process_match)?;
+        if options.format == Format::Text {
+            // Redraw in place: go back up to the first line of the previous output, and clear
+            if previous_lines > 0 {
+                print!("\x1b[{previous_lines}A");
+            }
+            print!("\x1b[J");
+            previous_lines = records.concat().lines().count();
+        }
+        print_records(options.format, &records);
+        std::io::stdout().flush()?;
+        std::thread::sleep(interval);
+    }
+}
+
+fn bytes_format_human(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
 }
 
 fn time_format_human(d: Duration) -> String {
@@ -0,0 +1,262 @@
+//! What we know of each supported command
+//!
+//! rm is not the only command going through a list of operands in order, walking each one's
+//! tree with directories open: `find -delete`, and recursive chmod, chown, chgrp and setfacl
+//! do the same. A [`CommandModel`] tells how to recognize such a process, how to find its
+//! operands on the command line, and how to tell its position from what it exposes. Other
+//! commands, like in-house cleanup tools, can be supported by adding a model to a
+//! [`Registry`], which also selects the processes to monitor with
+//! [`crate::procfs::ProcessMatch::Supported`].
+
+use crate::{
+    cmdline::{self, FindInvocation, RmInvocation, WalkInvocation, WalkSyntax},
+    error::Error,
+    procfs::{ProcSource, name_matches},
+    progress::{Position, Signals},
+};
+use std::ffi::OsString;
+
+/// Operands of a process, from its command line
+#[derive(Debug, Clone, Default)]
+pub struct Walk {
+    /// In the order they are processed
+    pub operands: Vec<OsString>,
+    /// The tree of each operand is walked
+    pub recursive: bool,
+}
+
+/// How to estimate the progress of a command
+pub trait CommandModel {
+    /// Command name, as matched by [`crate::procfs::ProcessMatch::Name`]
+    fn name(&self) -> &str;
+
+    /// The process runs this command. By default, when the model's name is its command name,
+    /// as for [`crate::procfs::ProcessMatch::Name`].
+    fn matches(&self, source: &dyn ProcSource, pid: u32) -> bool {
+        name_matches(source, pid, self.name())
+    }
+
+    /// Get the operands from the arguments, not including the command name
+    fn walk(&self, args: &[OsString]) -> Result<Walk, Error>;
+
+    /// Where the process is in its operands. By default, from the deepest directory it has
+    /// open in the furthest operand.
+    fn position(&self, signals: &Signals) -> Result<Position, Error> {
+        signals.open_fd(0).ok_or(Error::NoMatchingFd)
+    }
+
+    /// Monitored when no process selection is given
+    fn monitored_by_default(&self) -> bool {
+        true
+    }
+}
+
+/// `rm`, removing its operands in order
+pub struct Rm;
+
+impl CommandModel for Rm {
+    fn name(&self) -> &str {
+        "rm"
+    }
+
+    fn walk(&self, args: &[OsString]) -> Result<Walk, Error> {
+        let invocation = RmInvocation::parse(args);
+        Ok(Walk {
+            operands: invocation.operands,
+            recursive: invocation.flags.recursive,
+        })
+    }
+
+    /// Removed operands no longer exist, which also tells the position when rm is between
+    /// directories, or unlinking plain files it never opens
+    fn position(&self, signals: &Signals) -> Result<Position, Error> {
+        let removed = signals.removed_operands();
+        // rm cannot have removed arguments it hasn't reached yet: if more are gone than the
+        // open fd tells, the fd is lagging behind (or does not belong to the current argument)
+        match (signals.open_fd(removed.unwrap_or(0)), removed) {
+            (Some(position), _) => Ok(position),
+            (None, Some(removed)) => Ok(Position {
+                id: removed,
+                intra: None,
+                estimator: "existence",
+                current_unlinked: false,
+            }),
+            (None, None) => Err(Error::NoMatchingFd),
+        }
+    }
+}
+
+/// `find -delete` and `find -exec rm`, with the starting points as operands
+pub struct Find;
+
+impl CommandModel for Find {
+    fn name(&self) -> &str {
+        "find"
+    }
+
+    fn walk(&self, args: &[OsString]) -> Result<Walk, Error> {
+        let invocation = FindInvocation::parse(args);
+        if !invocation.deletes {
+            return Err(Error::NotDeleting);
+        }
+        Ok(Walk {
+            operands: invocation.starting_points,
+            recursive: true,
+        })
+    }
+
+    /// Only monitored with `--find`: most find processes only search
+    fn monitored_by_default(&self) -> bool {
+        false
+    }
+}
+
+/// A tool walking its operands without removing them, like chmod, chown, chgrp or setfacl
+pub struct Walker {
+    pub name: &'static str,
+    pub syntax: WalkSyntax,
+}
+
+impl CommandModel for Walker {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn walk(&self, args: &[OsString]) -> Result<Walk, Error> {
+        let invocation = WalkInvocation::parse(&self.syntax, args);
+        // Without a tree to walk, there is no directory open to tell the position
+        if !invocation.recursive {
+            return Err(Error::NotRecursive);
+        }
+        Ok(Walk {
+            operands: invocation.operands,
+            recursive: true,
+        })
+    }
+}
+
+/// The commands we know, to pick the model of a process
+pub struct Registry {
+    models: Vec<Box<dyn CommandModel>>,
+}
+
+impl Registry {
+    /// rm, find, chmod, chown, chgrp and setfacl
+    pub fn builtin() -> Self {
+        let walker = |name, syntax| -> Box<dyn CommandModel> { Box::new(Walker { name, syntax }) };
+        Self {
+            models: vec![
+                Box::new(Rm),
+                Box::new(Find),
+                walker("chmod", cmdline::CHMOD),
+                walker("chown", cmdline::CHOWN),
+                walker("chgrp", cmdline::CHOWN),
+                walker("setfacl", cmdline::SETFACL),
+            ],
+        }
+    }
+
+    /// Add a model, which takes precedence over those registered before
+    pub fn register(&mut self, model: Box<dyn CommandModel>) {
+        self.models.push(model);
+    }
+
+    pub fn models(&self) -> impl Iterator<Item = &dyn CommandModel> {
+        self.models.iter().map(Box::as_ref)
+    }
+
+    /// Model of a process. Processes no model recognizes, selected with `--exe` and the like,
+    /// are assumed to be an rm.
+    pub fn model_for(&self, source: &dyn ProcSource, pid: u32) -> &dyn CommandModel {
+        self.models
+            .iter()
+            .rev()
+            .find(|model| model.matches(source, pid))
+            .map_or(&Rm, Box::as_ref)
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::procfs::{FakeProc, FakeProcess, PidIterator, ProcessMatch};
+    use std::path::PathBuf;
+
+    /// An in-house tool, recognized by its exe path
+    struct Purge;
+
+    impl CommandModel for Purge {
+        fn name(&self) -> &str {
+            "purge"
+        }
+
+        fn matches(&self, source: &dyn ProcSource, pid: u32) -> bool {
+            source
+                .exe(pid)
+                .is_ok_and(|exe| exe.starts_with("/opt/purge"))
+        }
+
+        fn walk(&self, args: &[OsString]) -> Result<Walk, Error> {
+            Ok(Walk {
+                operands: args.to_vec(),
+                recursive: true,
+            })
+        }
+    }
+
+    fn processes() -> FakeProc {
+        let mut processes = FakeProc::default();
+        for (pid, exe, comm) in [
+            (1, "/usr/bin/rm", "rm"),
+            (2, "/opt/purge/bin/run", "run"),
+            (3, "/usr/bin/find", "find"),
+            (4, "/usr/bin/sleep", "sleep"),
+        ] {
+            processes.insert(
+                pid,
+                FakeProcess {
+                    exe: PathBuf::from(exe),
+                    comm: comm.to_string(),
+                    argv: vec![OsString::from(exe)],
+                    ..FakeProcess::default()
+                },
+            );
+        }
+        processes
+    }
+
+    fn selected(models: &Registry, process_match: &[ProcessMatch]) -> Vec<u32> {
+        PidIterator::new(&processes(), models, process_match)
+            .unwrap()
+            .collect()
+    }
+
+    #[test]
+    fn builtin_selection() {
+        let models = Registry::builtin();
+        assert_eq!(selected(&models, &[ProcessMatch::Supported]), [1]);
+        let find = [
+            ProcessMatch::Supported,
+            ProcessMatch::Model("find".to_string()),
+        ];
+        assert_eq!(selected(&models, &find), [1, 3]);
+    }
+
+    #[test]
+    fn registered_model() {
+        let mut models = Registry::builtin();
+        models.register(Box::new(Purge));
+        assert_eq!(selected(&models, &[ProcessMatch::Supported]), [1, 2]);
+        let source = processes();
+        assert_eq!(models.model_for(&source, 2).name(), "purge");
+        assert_eq!(models.model_for(&source, 1).name(), "rm");
+        // Selected otherwise, taken as an rm
+        assert_eq!(models.model_for(&source, 4).name(), "rm");
+    }
+}
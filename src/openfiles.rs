@@ -0,0 +1,202 @@
+//! Progress of any process through the regular files it has open
+//!
+//! Tools like cp, dd, gzip, sha256sum or shred go through their files sequentially, so the
+//! position of each open file, from `/proc/<pid>/fdinfo/<fd>`, compared to its size tells how
+//! far they are.
+
+use crate::{
+    clock::Clock,
+    error::Error,
+    path::display_path,
+    procfs::{
+        FdInfo, FdIterator, ProcSource, command_name, process_time_since_start, read_cmdline,
+    },
+    progress::{MovingAverage, RateTracker},
+    root::ProcessRoot,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// How a file was opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn from_flags(flags: u32) -> Self {
+        match flags & nix::libc::O_ACCMODE as u32 {
+            0 => Access::Read,
+            1 => Access::Write,
+            _ => Access::ReadWrite,
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::ReadWrite => write!(f, "read-write"),
+        }
+    }
+}
+
+/// A regular file open by a process
+#[derive(Debug, Clone)]
+pub struct OpenFile {
+    pub fd: u32,
+    /// As seen by the process
+    pub path: PathBuf,
+    pub access: Access,
+    pub position: u64,
+    pub size: u64,
+}
+
+impl OpenFile {
+    /// Fraction of the file before the position. Files being appended to are always at their
+    /// end, so this only means something for files read or overwritten.
+    pub fn fraction(&self) -> Option<f32> {
+        (self.size > 0).then(|| (self.position as f32 / self.size as f32).min(1.0))
+    }
+}
+
+/// The open files of a process, at the time it was sampled
+#[derive(Debug, Clone)]
+pub struct FileProgress {
+    pub pid: u32,
+    pub command: String,
+    /// Working directory, as seen by the process
+    pub cwd: PathBuf,
+    /// Sorted by fd
+    pub files: Vec<OpenFile>,
+    pub time_since_start: Duration,
+    /// Time since boot when sampled
+    pub sampled_at: Duration,
+}
+
+impl FileProgress {
+    pub fn sample(source: &dyn ProcSource, clock: &dyn Clock, pid: u32) -> Result<Self, Error> {
+        let root = ProcessRoot::new(source, pid)?;
+        let argv = read_cmdline(source, pid)?;
+        let mut files: Vec<OpenFile> = FdIterator::new(source, pid)?
+            .filter_map(|entry| {
+                // Closed since listed, or not a regular file
+                let size = source.fd_size(pid, entry.fd).ok()??;
+                let info = FdInfo::of(source, pid, entry.fd).ok()?;
+                Some(OpenFile {
+                    fd: entry.fd,
+                    path: root.to_process(&entry.path),
+                    access: Access::from_flags(info.flags),
+                    position: info.pos,
+                    size,
+                })
+            })
+            .collect();
+        files.sort_by_key(|file| file.fd);
+        Ok(Self {
+            pid,
+            command: command_name(&argv)
+                .map(|name| display_path(Path::new(name)))
+                .unwrap_or_default(),
+            cwd: root.to_process(&source.cwd(pid)?),
+            files,
+            time_since_start: process_time_since_start(source, clock, pid)?,
+            sampled_at: clock.since_boot()?,
+        })
+    }
+
+    /// Files that tell progress: those read if any, since a copy's output grows with its
+    /// position. Otherwise all of them, like a file being overwritten by shred.
+    pub fn tracked(&self) -> impl Iterator<Item = &OpenFile> {
+        let reading = self.files.iter().any(|file| file.access == Access::Read);
+        self.files
+            .iter()
+            .filter(move |file| !reading || file.access == Access::Read)
+    }
+
+    /// Position and size summed over the tracked files
+    pub fn total(&self) -> (u64, u64) {
+        self.tracked().fold((0, 0), |(position, size), file| {
+            (position + file.position.min(file.size), size + file.size)
+        })
+    }
+
+    pub fn fraction(&self) -> Option<f32> {
+        let (position, size) = self.total();
+        (size > 0).then(|| position as f32 / size as f32)
+    }
+
+    /// Bytes/s through the tracked files since the process started, assuming it only worked
+    /// on them
+    pub fn average_rate(&self) -> f32 {
+        let elapsed = self.time_since_start.as_secs_f32();
+        if elapsed > 0.0 {
+            self.total().0 as f32 / elapsed
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Moving average of the bytes/s through the tracked files, between successive samples.
+/// Files that are no longer open, or newly opened, don't count.
+pub struct ThroughputTracker {
+    /// Position of each tracked file, by fd and path
+    last_positions: HashMap<(u32, PathBuf), u64>,
+    last_sample: Duration,
+    rate: MovingAverage,
+}
+
+impl ThroughputTracker {
+    pub fn new(progress: &FileProgress) -> Self {
+        Self {
+            last_positions: Self::positions(progress),
+            last_sample: progress.sampled_at,
+            rate: MovingAverage::new(RateTracker::DEFAULT_WINDOW),
+        }
+    }
+
+    fn positions(progress: &FileProgress) -> HashMap<(u32, PathBuf), u64> {
+        progress
+            .tracked()
+            .map(|file| ((file.fd, file.path.clone()), file.position))
+            .collect()
+    }
+
+    pub fn update(&mut self, progress: &FileProgress) -> Option<f32> {
+        let elapsed = progress.sampled_at.saturating_sub(self.last_sample);
+        if !elapsed.is_zero() {
+            let positions = Self::positions(progress);
+            let advanced: u64 = positions
+                .iter()
+                .filter_map(|(file, position)| {
+                    Some(position.saturating_sub(*self.last_positions.get(file)?))
+                })
+                .sum();
+            self.rate
+                .update(advanced as f32 / elapsed.as_secs_f32(), elapsed);
+            self.last_positions = positions;
+            self.last_sample = progress.sampled_at;
+        }
+        self.rate.value()
+    }
+}
+
+/// Time left through the tracked files: at the recent rate if known, otherwise at the average
+/// rate since the process started
+pub fn eta(progress: &FileProgress, recent_rate: Option<f32>) -> Option<Duration> {
+    let (position, size) = progress.total();
+    let remaining = size.saturating_sub(position) as f32;
+    let rate = recent_rate.unwrap_or_else(|| progress.average_rate());
+    (rate > 0.0)
+        .then(|| Duration::try_from_secs_f32(remaining / rate).ok())
+        .flatten()
+}
@@ -0,0 +1,408 @@
+//! Weighing of operands by the number of items and bytes they contain
+//!
+//! Operands range from single files to trees of millions of files, so counting operands says
+//! little about the time left. [`Prescan`] walks the operands rm hasn't reached yet in a
+//! background thread with idle I/O priority, to measure progress in items removed instead.
+//!
+//! Walking everything can cost too much on slow disks, so a random sample of the operands
+//! can be weighed instead, and the weight of the others extrapolated with an interval.
+
+use crate::{
+    procfs::{IOPRIO_CLASS_IDLE, IOPRIO_CLASS_SHIFT, IOPRIO_WHO_PROCESS},
+    progress::Progress,
+};
+use std::{
+    fs, io,
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// What removing an operand involves
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Weight {
+    /// Directory entries, including the operand itself
+    pub items: u64,
+    /// Size of the files that aren't directories
+    pub bytes: u64,
+}
+
+impl AddAssign for Weight {
+    fn add_assign(&mut self, other: Self) {
+        self.items += other.items;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Weigh an operand, without following symlinks. Missing operands weigh nothing, and
+/// unreadable entries are skipped.
+fn weigh(path: &Path, stop: &AtomicBool) -> Weight {
+    let mut weight = Weight::default();
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        // std uses statx on Linux
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        weight.items += 1;
+        if !metadata.is_dir() {
+            weight.bytes += metadata.len();
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&path) else {
+            continue;
+        };
+        pending.extend(entries.filter_map(|entry| Some(entry.ok()?.path())));
+    }
+    weight
+}
+
+/// Give the calling thread idle I/O priority and the lowest CPU priority, so that the scan
+/// doesn't slow rm down
+fn lower_priority() -> io::Result<()> {
+    // SAFETY: these calls don't access memory. With an id of 0, they apply to the calling
+    // thread only.
+    unsafe {
+        if nix::libc::syscall(
+            nix::libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        if nix::libc::setpriority(nix::libc::PRIO_PROCESS, 0, 19) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Pick `count` distinct indices in `range`, in random order
+fn random_sample(range: std::ops::Range<usize>, count: usize) -> Vec<usize> {
+    // xorshift64*, good enough to pick operands
+    let mut state = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+        ^ u64::from(std::process::id())
+        | 1;
+    let mut next = move || {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    };
+    // Partial Fisher-Yates shuffle
+    let mut indices: Vec<usize> = range.collect();
+    let count = count.min(indices.len());
+    for i in 0..count {
+        let j = i + (next() % (indices.len() - i) as u64) as usize;
+        indices.swap(i, j);
+    }
+    indices.truncate(count);
+    indices
+}
+
+/// Normal quantile for a two-sided 95% interval
+const Z_95: f64 = 1.96;
+
+/// Progress in items, from the weights of the operands
+#[derive(Debug, Clone, Copy)]
+pub struct WeightedEstimate {
+    /// What is left to remove, once the operands to weigh are all weighed. Extrapolated when
+    /// sampling.
+    pub remaining: Option<Weight>,
+    /// Approximate 95% interval of the items left, when sampling
+    pub items_interval: Option<(u64, u64)>,
+    /// Fraction of the items remaining when weighing completed that were removed since
+    pub fraction: Option<f32>,
+    pub eta: Option<Duration>,
+    /// ETA for the bounds of the items interval
+    pub eta_interval: Option<(Duration, Duration)>,
+}
+
+/// Work left, partly extrapolated from the weighed operands
+struct Remaining {
+    weight: Weight,
+    /// Items in operands that were weighed
+    known_items: u64,
+    /// Variance of the extrapolated items, if there are enough weighed operands to tell
+    variance: Option<f64>,
+}
+
+/// Background weighing of the operands of a process
+pub struct Prescan {
+    /// Weight of each operand, once known
+    weights: Arc<Mutex<Vec<Option<Weight>>>>,
+    /// Operands being weighed
+    to_weigh: Vec<usize>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    /// Time since boot and items remaining when first known
+    baseline: Option<(Duration, f32)>,
+}
+
+impl Prescan {
+    /// Start weighing operands, from the first one not yet processed. With a sample size,
+    /// only that many of them, picked at random, are weighed.
+    pub fn start(operands: Vec<PathBuf>, first: usize, sample_size: Option<usize>) -> Self {
+        let weights = Arc::new(Mutex::new(vec![None; operands.len()]));
+        let remaining = first.min(operands.len())..operands.len();
+        let mut to_weigh = match sample_size {
+            Some(count) => random_sample(remaining, count),
+            None => remaining.collect(),
+        };
+        // Last operands first: rm won't have started removing them
+        to_weigh.sort_unstable_by(|a, b| b.cmp(a));
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let weights = Arc::clone(&weights);
+            let stop = Arc::clone(&stop);
+            let to_weigh = to_weigh.clone();
+            thread::spawn(move || {
+                // Still useful at normal priority
+                let _ = lower_priority();
+                for i in to_weigh {
+                    let weight = weigh(&operands[i], &stop);
+                    if stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    weights.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(weight);
+                }
+            })
+        };
+        Self {
+            weights,
+            to_weigh,
+            stop,
+            thread: Some(thread),
+            baseline: None,
+        }
+    }
+
+    /// Wait until all operands are weighed
+    pub fn wait(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    /// Items and bytes left, once the operands to weigh are all weighed.
+    ///
+    /// The current operand is weighed at scan time, and scaled by the fraction of its tree
+    /// left. Operands that weren't weighed are assumed to weigh the average of those that
+    /// were.
+    fn remaining(&self, progress: &Progress) -> Option<Remaining> {
+        let weights = self.weights.lock().unwrap_or_else(|e| e.into_inner());
+        let mut sample = Vec::new();
+        for &i in &self.to_weigh {
+            let weight = weights[i]?;
+            // Removed before we got to it, so it tells nothing about the others
+            if weight.items > 0 {
+                sample.push(weight);
+            }
+        }
+        let n = sample.len() as f64;
+        let mean_items = sample.iter().map(|w| w.items as f64).sum::<f64>() / n.max(1.0);
+        let mean_bytes = sample.iter().map(|w| w.bytes as f64).sum::<f64>() / n.max(1.0);
+        let sample_variance = (n >= 2.0).then(|| {
+            sample
+                .iter()
+                .map(|w| (w.items as f64 - mean_items).powi(2))
+                .sum::<f64>()
+                / (n - 1.0)
+        });
+        let mut known = Weight::default();
+        // Number of operands left to extrapolate, counting the current one partially
+        let mut unknown = 0.0;
+        for (i, weight) in weights.iter().enumerate().skip(progress.id) {
+            let left = if i == progress.id {
+                1.0 - f64::from(progress.intra.unwrap_or(0.0))
+            } else {
+                1.0
+            };
+            match weight {
+                Some(weight) => {
+                    known += Weight {
+                        items: (weight.items as f64 * left) as u64,
+                        bytes: (weight.bytes as f64 * left) as u64,
+                    }
+                }
+                None => unknown += left,
+            }
+        }
+        // The sum of the unknown weights varies around its estimate, and the mean it is
+        // estimated from is itself uncertain
+        let variance = if unknown > 0.0 {
+            sample_variance.map(|s2| s2 * (unknown + unknown * unknown / n))
+        } else {
+            Some(0.0)
+        };
+        Some(Remaining {
+            weight: Weight {
+                items: known.items + (unknown * mean_items) as u64,
+                bytes: known.bytes + (unknown * mean_bytes) as u64,
+            },
+            known_items: known.items,
+            variance,
+        })
+    }
+
+    /// Estimate progress in items. The rate is measured from the first sample where all
+    /// operands to weigh were weighed.
+    pub fn estimate(&mut self, progress: &Progress) -> WeightedEstimate {
+        let Some(remaining) = self.remaining(progress) else {
+            return WeightedEstimate {
+                remaining: None,
+                items_interval: None,
+                fraction: None,
+                eta: None,
+                eta_interval: None,
+            };
+        };
+        let items = remaining.weight.items as f32;
+        let (since, initial) = *self.baseline.get_or_insert((progress.sampled_at, items));
+        let removed = (initial - items).max(0.0);
+        let elapsed = progress.sampled_at.saturating_sub(since).as_secs_f32();
+        // Items per second
+        let rate = (removed > 0.0 && elapsed > 0.0).then(|| removed / elapsed);
+        let eta_for = |items: f32| Duration::try_from_secs_f32(items / rate?).ok();
+        let items_interval =
+            remaining
+                .variance
+                .filter(|&variance| variance > 0.0)
+                .map(|variance| {
+                    let margin = Z_95 * variance.sqrt();
+                    let items = remaining.weight.items as f64;
+                    (
+                        ((items - margin) as u64).max(remaining.known_items),
+                        (items + margin) as u64,
+                    )
+                });
+        WeightedEstimate {
+            remaining: Some(remaining.weight),
+            items_interval,
+            fraction: (initial > 0.0).then(|| removed / initial),
+            eta: eta_for(items),
+            eta_interval: items_interval
+                .and_then(|(low, high)| Some((eta_for(low as f32)?, eta_for(high as f32)?))),
+        }
+    }
+}
+
+impl Drop for Prescan {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prescan(weights: Vec<Option<Weight>>, to_weigh: Vec<usize>) -> Prescan {
+        Prescan {
+            weights: Arc::new(Mutex::new(weights)),
+            to_weigh,
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+            baseline: None,
+        }
+    }
+
+    fn items(items: u64) -> Option<Weight> {
+        Some(Weight {
+            items,
+            bytes: items * 10,
+        })
+    }
+
+    fn progress(id: usize, intra: Option<f32>) -> Progress {
+        Progress {
+            pid: 42,
+            command: "rm".to_string(),
+            cwd: PathBuf::from("/data"),
+            host_cwd: None,
+            other_mount_namespace: false,
+            id,
+            intra,
+            args: 5,
+            operands: Vec::new(),
+            estimator: "open fd",
+            current_unlinked: false,
+            time_since_start: Duration::from_secs(30),
+            sampled_at: Duration::from_secs(80),
+        }
+    }
+
+    #[test]
+    fn sample_of_distinct_indices() {
+        let mut sample = random_sample(3..10, 4);
+        assert_eq!(sample.len(), 4);
+        assert!(sample.iter().all(|i| (3..10).contains(i)));
+        sample.sort_unstable();
+        sample.dedup();
+        assert_eq!(sample.len(), 4);
+        // More than there are: all of them
+        let mut sample = random_sample(3..10, 20);
+        sample.sort_unstable();
+        assert_eq!(sample, (3..10).collect::<Vec<_>>());
+        assert!(random_sample(5..5, 2).is_empty());
+    }
+
+    #[test]
+    fn all_weighed() {
+        let prescan = prescan(
+            vec![items(4), items(10), items(20), items(30)],
+            vec![3, 2, 1, 0],
+        );
+        let remaining = prescan.remaining(&progress(1, Some(0.5))).unwrap();
+        assert_eq!(remaining.weight, items(55).unwrap());
+        assert_eq!(remaining.known_items, 55);
+        assert_eq!(remaining.variance, Some(0.0));
+        // The fraction of the current tree is unknown
+        let remaining = prescan.remaining(&progress(1, None)).unwrap();
+        assert_eq!(remaining.known_items, 60);
+    }
+
+    #[test]
+    fn not_weighed_yet() {
+        let mut prescan = prescan(vec![items(4), None, items(20)], vec![2, 1]);
+        assert!(prescan.remaining(&progress(0, None)).is_none());
+        assert_eq!(prescan.estimate(&progress(0, None)).remaining, None);
+    }
+
+    #[test]
+    fn extrapolated_from_sample() {
+        let mut prescan = prescan(vec![None, items(10), None, items(30), None], vec![3, 1]);
+        let remaining = prescan.remaining(&progress(0, None)).unwrap();
+        // 3 operands at the mean of 20 items, and the 40 items weighed
+        assert_eq!(remaining.weight, items(100).unwrap());
+        assert_eq!(remaining.known_items, 40);
+        // Sample variance of 200, for a sum of 3 with a mean from 2
+        assert_eq!(remaining.variance, Some(200.0 * (3.0 + 9.0 / 2.0)));
+        let estimate = prescan.estimate(&progress(0, None));
+        // 100 ± 76, but the weighed operands are still there
+        assert_eq!(estimate.items_interval, Some((40, 175)));
+        assert_eq!(estimate.eta, None);
+    }
+
+    #[test]
+    fn removed_before_weighed() {
+        let prescan = prescan(vec![None, items(10), None, items(0), None], vec![3, 1]);
+        let remaining = prescan.remaining(&progress(0, None)).unwrap();
+        // Only the operand still there tells the mean
+        assert_eq!(remaining.weight.items, 10 + 3 * 10);
+        assert_eq!(remaining.known_items, 10);
+        assert_eq!(remaining.variance, None);
+    }
+}
@@ -0,0 +1,670 @@
+//! Access to process information from procfs
+//!
+//! Everything goes through the [`ProcSource`] trait, so that estimations can run on a procfs
+//! mount ([`Procfs`]) as well as on in-memory data ([`FakeProc`]).
+
+use crate::{
+    clock::{Clock, ticks_to_duration},
+    error::Error,
+    model::Registry,
+};
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    fmt, fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+/// `ioprio_get` and `ioprio_set` target a process, or a thread
+pub const IOPRIO_WHO_PROCESS: nix::libc::c_long = 1;
+/// The class of an I/O priority is above its level
+pub const IOPRIO_CLASS_SHIFT: u32 = 13;
+pub const IOPRIO_CLASS_IDLE: nix::libc::c_long = 3;
+
+/// Source of the per-process information we need
+pub trait ProcSource {
+    /// List all pids
+    fn pids(&self) -> io::Result<Vec<u32>>;
+    /// Target of the `exe` link
+    fn exe(&self, pid: u32) -> io::Result<PathBuf>;
+    /// Target of the `cwd` link
+    fn cwd(&self, pid: u32) -> io::Result<PathBuf>;
+    /// Contents of `comm`, including its trailing newline
+    fn comm(&self, pid: u32) -> io::Result<Vec<u8>>;
+    /// Raw contents of `cmdline`: NUL-terminated arguments
+    fn cmdline(&self, pid: u32) -> io::Result<Vec<u8>>;
+    /// Contents of `stat`
+    fn stat(&self, pid: u32) -> io::Result<String>;
+    /// Contents of `io`, only readable for our own processes
+    fn io(&self, pid: u32) -> io::Result<String>;
+    /// Contents of `wchan`: the kernel function the process is blocked in, or `0`
+    fn wchan(&self, pid: u32) -> io::Result<String>;
+    /// I/O priority, as returned by `ioprio_get`
+    fn ioprio(&self, pid: u32) -> io::Result<i32>;
+    /// Open file descriptors, with the target of their link
+    fn fds(&self, pid: u32) -> io::Result<Vec<(u32, PathBuf)>>;
+    /// Contents of `fdinfo/<fd>`: position and flags of an open file
+    fn fdinfo(&self, pid: u32, fd: u32) -> io::Result<String>;
+    /// Size of the file open on a descriptor, if it is a regular file
+    fn fd_size(&self, pid: u32, fd: u32) -> io::Result<Option<u64>>;
+    /// Target of the `root` link: root directory of the process, as we see it
+    fn root(&self, pid: u32) -> io::Result<PathBuf>;
+    /// Target of the `ns/mnt` link, which identifies the mount namespace
+    fn mount_namespace(&self, pid: u32) -> io::Result<PathBuf>;
+    /// Mount namespace we run in
+    fn own_mount_namespace(&self) -> io::Result<PathBuf>;
+    /// Directory through which we can access the filesystem of the process, even from another
+    /// mount namespace
+    fn root_access(&self, pid: u32) -> PathBuf;
+    /// Names of the entries of a directory we can access, in listing order
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<OsString>>;
+    /// Whether a path we can access exists, without following a final symlink
+    fn exists(&self, path: &Path) -> io::Result<bool>;
+}
+
+/// A procfs mount: the real `/proc` by default, or for instance the host's procfs mounted
+/// in a container, or a snapshot of one.
+#[derive(Debug, Clone)]
+pub struct Procfs {
+    root: PathBuf,
+}
+
+impl Default for Procfs {
+    fn default() -> Self {
+        Self::new("/proc")
+    }
+}
+
+impl Procfs {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, pid: u32, name: &str) -> PathBuf {
+        self.root.join(pid.to_string()).join(name)
+    }
+
+    /// Whether pids in this procfs are ours: `self` links to our own pid in it
+    fn in_own_pid_namespace(&self) -> bool {
+        fs::read_link(self.root.join("self"))
+            .is_ok_and(|link| link.as_os_str() == std::process::id().to_string().as_str())
+    }
+}
+
+impl ProcSource for Procfs {
+    fn pids(&self) -> io::Result<Vec<u32>> {
+        Ok(fs::read_dir(&self.root)?
+            .filter_map(|res| res.ok()) // discard errors for individual files
+            .map(|f| f.file_name()) // keep only basename from path
+            .filter_map(|f| f.to_str()?.parse::<u32>().ok()) // only pids, as integers
+            .collect())
+    }
+    fn exe(&self, pid: u32) -> io::Result<PathBuf> {
+        fs::read_link(self.path(pid, "exe"))
+    }
+    fn cwd(&self, pid: u32) -> io::Result<PathBuf> {
+        fs::read_link(self.path(pid, "cwd"))
+    }
+    fn comm(&self, pid: u32) -> io::Result<Vec<u8>> {
+        fs::read(self.path(pid, "comm"))
+    }
+    fn cmdline(&self, pid: u32) -> io::Result<Vec<u8>> {
+        fs::read(self.path(pid, "cmdline"))
+    }
+    fn stat(&self, pid: u32) -> io::Result<String> {
+        fs::read_to_string(self.path(pid, "stat"))
+    }
+    fn io(&self, pid: u32) -> io::Result<String> {
+        fs::read_to_string(self.path(pid, "io"))
+    }
+    fn wchan(&self, pid: u32) -> io::Result<String> {
+        fs::read_to_string(self.path(pid, "wchan"))
+    }
+    fn ioprio(&self, pid: u32) -> io::Result<i32> {
+        // Not from procfs: the pid must mean the same to the kernel as in our procfs
+        if !self.in_own_pid_namespace() {
+            return Err(io::Error::other("procfs from another pid namespace"));
+        }
+        // SAFETY: this call doesn't access memory
+        let ioprio = unsafe {
+            nix::libc::syscall(
+                nix::libc::SYS_ioprio_get,
+                IOPRIO_WHO_PROCESS,
+                nix::libc::c_long::from(pid),
+            )
+        };
+        if ioprio < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ioprio as i32)
+    }
+    fn fds(&self, pid: u32) -> io::Result<Vec<(u32, PathBuf)>> {
+        Ok(fs::read_dir(self.path(pid, "fd"))?
+            .filter_map(|res| res.ok())
+            .filter_map(|fd| {
+                // fds can be closed while we list them
+                let link = fs::read_link(fd.path()).ok()?;
+                Some((fd.file_name().to_str()?.parse().ok()?, link))
+            })
+            .collect())
+    }
+    fn fdinfo(&self, pid: u32, fd: u32) -> io::Result<String> {
+        fs::read_to_string(self.path(pid, &format!("fdinfo/{fd}")))
+    }
+    fn fd_size(&self, pid: u32, fd: u32) -> io::Result<Option<u64>> {
+        // Follows the link to the open file, even if it was unlinked or is in another mount
+        // namespace
+        let metadata = fs::metadata(self.path(pid, &format!("fd/{fd}")))?;
+        Ok(metadata.is_file().then_some(metadata.len()))
+    }
+    fn root(&self, pid: u32) -> io::Result<PathBuf> {
+        fs::read_link(self.path(pid, "root"))
+    }
+    fn mount_namespace(&self, pid: u32) -> io::Result<PathBuf> {
+        fs::read_link(self.path(pid, "ns/mnt"))
+    }
+    fn own_mount_namespace(&self) -> io::Result<PathBuf> {
+        fs::read_link(self.root.join("self/ns/mnt"))
+    }
+    fn root_access(&self, pid: u32) -> PathBuf {
+        self.path(pid, "root")
+    }
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<OsString>> {
+        Ok(fs::read_dir(dir)?
+            .filter_map(|res| res.ok())
+            .map(|entry| entry.file_name())
+            .collect())
+    }
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        match fs::symlink_metadata(path) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// A process, as described to [`FakeProc`]
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FakeProcess {
+    pub exe: PathBuf,
+    pub cwd: PathBuf,
+    /// Without trailing newline
+    pub comm: String,
+    pub argv: Vec<OsString>,
+    pub stat: String,
+    pub io: String,
+    pub wchan: String,
+    /// `0`, the default, means no class was set
+    pub ioprio: i32,
+    pub fds: Vec<(u32, PathBuf)>,
+    /// Contents of `fdinfo/<fd>`, for the fds that have it
+    pub fdinfo: Vec<(u32, String)>,
+    /// Size of the fds open on regular files
+    pub fd_sizes: Vec<(u32, u64)>,
+    /// Root directory, `/` if empty
+    pub root: PathBuf,
+    /// Directory through which the filesystem of the process is accessed, `root` if empty
+    pub root_access: PathBuf,
+    /// Mount namespace, the same as [`FakeProc::mount_namespace`] if empty
+    pub mount_namespace: String,
+}
+
+/// In-memory process information
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FakeProc {
+    pub processes: BTreeMap<u32, FakeProcess>,
+    /// Our own mount namespace
+    pub mount_namespace: String,
+    /// Entries of the directories that can be listed, by the path we access them through
+    pub listings: BTreeMap<PathBuf, Vec<OsString>>,
+    /// Whether paths exist, by the path we access them through. Others can't be checked.
+    pub existence: BTreeMap<PathBuf, bool>,
+}
+
+impl FakeProc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, pid: u32, process: FakeProcess) {
+        self.processes.insert(pid, process);
+    }
+
+    fn process(&self, pid: u32) -> io::Result<&FakeProcess> {
+        self.processes
+            .get(&pid)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no process {pid}")))
+    }
+}
+
+impl ProcSource for FakeProc {
+    fn pids(&self) -> io::Result<Vec<u32>> {
+        Ok(self.processes.keys().copied().collect())
+    }
+    fn exe(&self, pid: u32) -> io::Result<PathBuf> {
+        Ok(self.process(pid)?.exe.clone())
+    }
+    fn cwd(&self, pid: u32) -> io::Result<PathBuf> {
+        Ok(self.process(pid)?.cwd.clone())
+    }
+    fn comm(&self, pid: u32) -> io::Result<Vec<u8>> {
+        Ok(format!("{}\n", self.process(pid)?.comm).into_bytes())
+    }
+    fn cmdline(&self, pid: u32) -> io::Result<Vec<u8>> {
+        let mut cmdline = Vec::new();
+        for arg in &self.process(pid)?.argv {
+            cmdline.extend_from_slice(arg.as_bytes());
+            cmdline.push(b'\0');
+        }
+        Ok(cmdline)
+    }
+    fn stat(&self, pid: u32) -> io::Result<String> {
+        Ok(self.process(pid)?.stat.clone())
+    }
+    fn io(&self, pid: u32) -> io::Result<String> {
+        Ok(self.process(pid)?.io.clone())
+    }
+    fn wchan(&self, pid: u32) -> io::Result<String> {
+        Ok(self.process(pid)?.wchan.clone())
+    }
+    fn ioprio(&self, pid: u32) -> io::Result<i32> {
+        Ok(self.process(pid)?.ioprio)
+    }
+    fn fds(&self, pid: u32) -> io::Result<Vec<(u32, PathBuf)>> {
+        Ok(self.process(pid)?.fds.clone())
+    }
+    fn fdinfo(&self, pid: u32, fd: u32) -> io::Result<String> {
+        self.process(pid)?
+            .fdinfo
+            .iter()
+            .find(|(n, _)| *n == fd)
+            .map(|(_, fdinfo)| fdinfo.clone())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no fd {fd}")))
+    }
+    fn fd_size(&self, pid: u32, fd: u32) -> io::Result<Option<u64>> {
+        let process = self.process(pid)?;
+        if !process.fds.iter().any(|(n, _)| *n == fd) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no fd {fd}"),
+            ));
+        }
+        Ok(process
+            .fd_sizes
+            .iter()
+            .find(|(n, _)| *n == fd)
+            .map(|(_, size)| *size))
+    }
+    fn root(&self, pid: u32) -> io::Result<PathBuf> {
+        let root = &self.process(pid)?.root;
+        Ok(if root.as_os_str().is_empty() {
+            PathBuf::from("/")
+        } else {
+            root.clone()
+        })
+    }
+    fn mount_namespace(&self, pid: u32) -> io::Result<PathBuf> {
+        let namespace = &self.process(pid)?.mount_namespace;
+        Ok(PathBuf::from(if namespace.is_empty() {
+            &self.mount_namespace
+        } else {
+            namespace
+        }))
+    }
+    fn own_mount_namespace(&self) -> io::Result<PathBuf> {
+        Ok(PathBuf::from(&self.mount_namespace))
+    }
+    fn root_access(&self, pid: u32) -> PathBuf {
+        match self.process(pid) {
+            Ok(process) if !process.root_access.as_os_str().is_empty() => {
+                process.root_access.clone()
+            }
+            _ => self.root(pid).unwrap_or_else(|_| PathBuf::from("/")),
+        }
+    }
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<OsString>> {
+        self.listings.get(dir).cloned().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no listing of {}", dir.display()),
+            )
+        })
+    }
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        self.existence
+            .get(path)
+            .copied()
+            .ok_or_else(|| io::Error::other(format!("existence of {} unknown", path.display())))
+    }
+}
+
+/// How to recognize a process to monitor
+#[derive(Clone, Debug)]
+pub enum ProcessMatch {
+    /// Command name, compared to the exe and `argv[0]` basenames, comm, and multicall applet
+    Name(String),
+    /// Substring of the exe path
+    Exe(String),
+    /// Exact comm, as truncated by the kernel
+    Comm(String),
+    Pid(u32),
+    /// Recognized by one of the models monitored by default
+    Supported,
+    /// Recognized by the model of a given name
+    Model(String),
+}
+
+impl ProcessMatch {
+    pub fn matches(&self, source: &dyn ProcSource, models: &Registry, pid: u32) -> bool {
+        match self {
+            ProcessMatch::Name(name) => name_matches(source, pid, name),
+            // An empty pattern matches nothing
+            ProcessMatch::Exe(pattern) if pattern.is_empty() => false,
+            ProcessMatch::Exe(pattern) => source.exe(pid).is_ok_and(|path| {
+                path.as_os_str()
+                    .as_bytes()
+                    .windows(pattern.len())
+                    .any(|w| w == pattern.as_bytes())
+            }),
+            ProcessMatch::Comm(name) => {
+                source.comm(pid).is_ok_and(|comm| comm_matches(&comm, name))
+            }
+            ProcessMatch::Pid(p) => pid == *p,
+            ProcessMatch::Supported => models
+                .models()
+                .any(|model| model.monitored_by_default() && model.matches(source, pid)),
+            ProcessMatch::Model(name) => models
+                .models()
+                .any(|model| model.name() == name && model.matches(source, pid)),
+        }
+    }
+}
+
+/// The process runs a command, as told by its exe and `argv[0]` basenames, comm, or multicall
+/// applet
+pub fn name_matches(source: &dyn ProcSource, pid: u32, name: &str) -> bool {
+    source
+        .exe(pid)
+        .is_ok_and(|exe| exe.file_name() == Some(OsStr::new(name)))
+        || source.comm(pid).is_ok_and(|comm| comm_matches(&comm, name))
+        || read_cmdline(source, pid).is_ok_and(|argv| command_name(&argv) == Some(OsStr::new(name)))
+}
+
+fn comm_matches(comm: &[u8], name: &str) -> bool {
+    // The kernel truncates comm to TASK_COMM_LEN - 1 bytes
+    let comm = comm.strip_suffix(b"\n").unwrap_or(comm);
+    comm == &name.as_bytes()[..name.len().min(15)]
+}
+
+/// Multicall binaries, which take the applet name as first argument
+const MULTICALL: &[&str] = &["busybox", "coreutils"];
+
+fn is_multicall(argv: &[OsString]) -> bool {
+    argv.first()
+        .and_then(|argv0| Path::new(argv0).file_name())
+        .is_some_and(|name| MULTICALL.iter().any(|m| name == *m))
+}
+
+/// Name of the command being run: `argv[0]` basename, or the applet of a multicall binary
+pub fn command_name(argv: &[OsString]) -> Option<&OsStr> {
+    match argv {
+        [_, applet, ..] if is_multicall(argv) => Some(
+            // GNU coreutils single binary
+            OsStr::from_bytes(
+                applet
+                    .as_bytes()
+                    .strip_prefix(b"--coreutils-prog=")
+                    .unwrap_or(applet.as_bytes()),
+            ),
+        ),
+        [argv0, ..] => Path::new(argv0).file_name(),
+        [] => None,
+    }
+}
+
+/// Arguments of the command, skipping its name (and the multicall binary name, if any)
+pub fn command_args(argv: &[OsString]) -> &[OsString] {
+    match argv {
+        [_, _, args @ ..] if is_multicall(argv) => args,
+        [_, args @ ..] => args,
+        [] => &[],
+    }
+}
+
+/// Split a process's cmdline into its arguments
+pub fn read_cmdline(source: &dyn ProcSource, pid: u32) -> io::Result<Vec<OsString>> {
+    let cmdline_content = source.cmdline(pid)?;
+    Ok(cmdline_content
+        .strip_suffix(b"\0")
+        .unwrap_or(&cmdline_content)
+        .split(|&c| c == b'\0')
+        .map(|arg| OsStr::from_bytes(arg).to_owned())
+        .collect())
+}
+
+pub struct PidIterator<'a> {
+    pids: Box<dyn Iterator<Item = u32> + 'a>,
+}
+impl<'a> PidIterator<'a> {
+    /// Processes matching any of `process_match`, or all processes if it is empty
+    pub fn new(
+        source: &'a dyn ProcSource,
+        models: &'a Registry,
+        process_match: &[ProcessMatch],
+    ) -> Result<Self, Error> {
+        let process_match = process_match.to_vec();
+        Ok(Self {
+            pids: Box::new(
+                source
+                    .pids()
+                    .map_err(|e| Error::System(format!("listing processes: {e}")))?
+                    .into_iter()
+                    .filter(move |&pid| {
+                        process_match.is_empty()
+                            || process_match.iter().any(|m| m.matches(source, models, pid))
+                    }),
+            ),
+        })
+    }
+}
+impl Iterator for PidIterator<'_> {
+    type Item = u32;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.pids.next()
+    }
+}
+
+/// An open file descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FdEntry {
+    pub fd: u32,
+    /// Path of the open file, without the deleted marker
+    pub path: PathBuf,
+    /// The file was unlinked while open: its link ended with ` (deleted)`
+    pub deleted: bool,
+}
+
+impl FdEntry {
+    const DELETED_MARKER: &[u8] = b" (deleted)";
+
+    pub fn from_link(fd: u32, link: PathBuf) -> Self {
+        match link
+            .as_os_str()
+            .as_bytes()
+            .strip_suffix(Self::DELETED_MARKER)
+        {
+            Some(path) => FdEntry {
+                fd,
+                path: PathBuf::from(OsStr::from_bytes(path)),
+                deleted: true,
+            },
+            None => FdEntry {
+                fd,
+                path: link,
+                deleted: false,
+            },
+        }
+    }
+}
+
+/// Position and flags of an open file, from `fdinfo/<fd>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdInfo {
+    /// Offset of the next read or write
+    pub pos: u64,
+    /// `open` flags
+    pub flags: u32,
+}
+
+impl FdInfo {
+    pub fn parse(fdinfo: &str) -> Option<Self> {
+        let field = |name: &str| {
+            fdinfo.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                (key == name).then(|| value.trim())
+            })
+        };
+        Some(Self {
+            pos: field("pos")?.parse().ok()?,
+            // Octal, like in the open(2) man page
+            flags: u32::from_str_radix(field("flags")?, 8).ok()?,
+        })
+    }
+
+    pub fn of(source: &dyn ProcSource, pid: u32, fd: u32) -> Result<Self, Error> {
+        Self::parse(&source.fdinfo(pid, fd)?)
+            .ok_or_else(|| Error::Parse(format!("invalid fdinfo {fd} of {pid}")))
+    }
+}
+
+/// Iterate over the fds of a process that point to a path: not to a pipe, socket, etc.
+pub struct FdIterator {
+    fds: std::vec::IntoIter<(u32, PathBuf)>,
+}
+
+impl FdIterator {
+    pub fn new(source: &dyn ProcSource, pid: u32) -> Result<Self, Error> {
+        Ok(FdIterator {
+            fds: source.fds(pid)?.into_iter(),
+        })
+    }
+}
+impl Iterator for FdIterator {
+    type Item = FdEntry;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fds
+            .find(|(_, link)| link.as_os_str().as_bytes().starts_with(b"/"))
+            .map(|(fd, link)| FdEntry::from_link(fd, link))
+    }
+}
+
+/// Fields of `/proc/<pid>/stat`
+pub struct Stat {
+    pid: u32,
+    /// From the state on, field 3 in proc(5)
+    fields: Vec<String>,
+}
+
+impl Stat {
+    pub fn read(source: &dyn ProcSource, pid: u32) -> Result<Self, Error> {
+        Self::parse(pid, &source.stat(pid)?)
+    }
+
+    pub fn parse(pid: u32, stat: &str) -> Result<Self, Error> {
+        // comm can contain spaces and parentheses, fields are after its closing one
+        let (_, fields) = stat
+            .rsplit_once(')')
+            .ok_or_else(|| Error::Parse(format!("no comm in stat of {pid}")))?;
+        Ok(Self {
+            pid,
+            fields: fields.split_ascii_whitespace().map(String::from).collect(),
+        })
+    }
+
+    /// Field by its number in proc(5)
+    pub fn field(&self, number: usize) -> Result<&str, Error> {
+        number
+            .checked_sub(3)
+            .and_then(|i| self.fields.get(i))
+            .map(String::as_str)
+            .ok_or_else(|| Error::Parse(format!("no field {number} in stat of {}", self.pid)))
+    }
+
+    pub fn number<T: FromStr>(&self, number: usize) -> Result<T, Error>
+    where
+        T::Err: fmt::Display,
+    {
+        self.field(number)?
+            .parse()
+            .map_err(|e| Error::Parse(format!("stat field {number} of {}: {e}", self.pid)))
+    }
+}
+
+/// Time elapsed since the process started, with clock tick precision
+pub fn process_time_since_start(
+    source: &dyn ProcSource,
+    clock: &dyn Clock,
+    pid: u32,
+) -> Result<Duration, Error> {
+    let start_time_after_boot = Stat::read(source, pid)?.number(22)?;
+    let process_start_time_after_boot =
+        ticks_to_duration(start_time_after_boot, clock.ticks_per_second()?);
+    // Saturate: a process that just started can be ahead of our clock reading
+    Ok(clock
+        .since_boot()?
+        .saturating_sub(process_start_time_after_boot))
+}
+
+/// Parent of a process, from `stat`
+pub fn parent_pid(source: &dyn ProcSource, pid: u32) -> Result<u32, Error> {
+    Stat::read(source, pid)?.number(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fd_entry_from_link() {
+        let entry = FdEntry::from_link(3, PathBuf::from("/data/a"));
+        assert_eq!(entry.path, Path::new("/data/a"));
+        assert!(!entry.deleted);
+        let entry = FdEntry::from_link(4, PathBuf::from("/data/b (deleted)"));
+        assert_eq!(entry.fd, 4);
+        assert_eq!(entry.path, Path::new("/data/b"));
+        assert!(entry.deleted);
+    }
+
+    #[test]
+    fn stat_fields_after_comm() {
+        let stat = Stat::parse(42, "42 (my rm) (x) S 7 42 42 0 -1").unwrap();
+        assert_eq!(stat.field(3).unwrap(), "S");
+        assert_eq!(stat.number::<u32>(4).unwrap(), 7);
+        assert!(stat.field(12).is_err());
+        assert!(Stat::parse(42, "42 rm S").is_err());
+    }
+
+    #[test]
+    fn fdinfo() {
+        let info = FdInfo::parse("pos:\t1024\nflags:\t0100002\nmnt_id:\t25\n").unwrap();
+        assert_eq!(info.pos, 1024);
+        assert_eq!(info.flags, 0o100002);
+    }
+
+    #[test]
+    fn ioprio_from_foreign_procfs() {
+        assert!(Procfs::default().ioprio(std::process::id()).is_ok());
+        // The host's procfs, seen from a container
+        let root = std::env::temp_dir().join(format!("progressrm-procfs-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let _ = fs::remove_file(root.join("self"));
+        std::os::unix::fs::symlink("1", root.join("self")).unwrap();
+        let ioprio = Procfs::new(&root).ioprio(std::process::id());
+        fs::remove_dir_all(&root).unwrap();
+        assert!(ioprio.is_err());
+    }
+}
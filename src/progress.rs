@@ -0,0 +1,712 @@
+//! Progress estimation of a process through its arguments
+//!
+//! rm removes its operands in order. The other commands in [`crate::model`] are handled the
+//! same way, like `find -delete` with its starting points as operands.
+
+use crate::{
+    clock::Clock,
+    error::Error,
+    model::{Registry, Walk},
+    path::{display_path, normalize_lexically},
+    procfs::{
+        FdEntry, FdIterator, ProcSource, command_args, command_name, process_time_since_start,
+        read_cmdline,
+    },
+    root::ProcessRoot,
+};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, hash_map::Entry},
+    ffi::OsString,
+    io,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+/// Progress of one process through its operands, at the time it was sampled
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub pid: u32,
+    pub command: String,
+    /// Working directory, as seen by the process
+    pub cwd: PathBuf,
+    /// Working directory as seen from here, if the process is in a chroot or container
+    pub host_cwd: Option<PathBuf>,
+    pub other_mount_namespace: bool,
+    /// Index of the argument being processed
+    pub id: usize,
+    /// Fraction of the current argument's tree processed since monitoring began, unknown until
+    /// its listing was taken in an earlier sample
+    pub intra: Option<f32>,
+    pub args: usize,
+    /// Paths through which we can access the operands
+    pub operands: Vec<PathBuf>,
+    pub estimator: &'static str,
+    /// The open directory rm is in was already unlinked
+    pub current_unlinked: bool,
+    pub time_since_start: Duration,
+    /// Time since boot when sampled
+    pub sampled_at: Duration,
+}
+
+impl Progress {
+    /// Number of arguments processed, including the fraction of the current one if known
+    pub fn position(&self) -> f32 {
+        self.id as f32 + self.intra.unwrap_or(0.0)
+    }
+}
+
+/// Samples the progress of processes
+pub struct Sampler<'a> {
+    pub source: &'a dyn ProcSource,
+    pub clock: &'a dyn Clock,
+    /// Look at operands on the filesystem. To disable when it doesn't match the process
+    /// information, like when replaying a trace. Directory listings and the existence of
+    /// operands are still checked through the source, which has them in a trace.
+    pub probe_filesystem: bool,
+    /// Keep a copy of the directory listings and existence checks taken, to record them in a
+    /// trace
+    pub record_filesystem: bool,
+    /// Models of the commands we can follow
+    pub models: Registry,
+    /// Listings of the directories each process is in, as first seen, for
+    /// [`intra_arg_progress`]
+    listings: RefCell<HashMap<u32, HashMap<PathBuf, Vec<OsString>>>>,
+    /// Taken since last taken out, with `record_filesystem`
+    recorded: RefCell<FilesystemRecord>,
+}
+
+/// What estimators looked at on the filesystem, to record in a trace
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FilesystemRecord {
+    /// Directory listings, by the path they were taken through
+    pub listings: BTreeMap<PathBuf, Vec<OsString>>,
+    /// Existence checks, by the path they were made through
+    pub existence: BTreeMap<PathBuf, bool>,
+}
+
+impl<'a> Sampler<'a> {
+    pub fn new(source: &'a dyn ProcSource, clock: &'a dyn Clock) -> Self {
+        Self {
+            source,
+            clock,
+            probe_filesystem: true,
+            models: Registry::builtin(),
+            record_filesystem: false,
+            listings: RefCell::new(HashMap::new()),
+            recorded: RefCell::default(),
+        }
+    }
+
+    /// Directory listings and existence checks taken since the last call, with
+    /// `record_filesystem`
+    pub fn take_recorded(&self) -> FilesystemRecord {
+        self.recorded.take()
+    }
+
+    /// Find where a process is in its list of arguments
+    pub fn sample(&self, pid: u32) -> Result<Progress, Error> {
+        let Sampler { source, clock, .. } = *self;
+        let root = ProcessRoot::new(source, pid)?;
+        let cwd = root.to_process(&source.cwd(pid)?);
+        let argv = read_cmdline(source, pid)?;
+        let model = self.models.model_for(source, pid);
+        let Walk {
+            operands,
+            recursive,
+        } = model.walk(command_args(&argv))?;
+        let cmdline: Vec<PathBuf> = operands
+            .iter()
+            .map(|s| {
+                normalize_lexically(&cwd.join(s))
+                    .map_err(|_| Error::UnnormalizableOperand(s.to_owned()))
+            })
+            .collect::<Result<_, _>>()?;
+        let sampled_at = clock.since_boot()?;
+        let time_since_start = process_time_since_start(source, clock, pid)?;
+        let fds: Vec<FdEntry> = FdIterator::new(source, pid)?
+            .map(|entry| FdEntry {
+                path: root.to_process(&entry.path),
+                ..entry
+            })
+            .collect();
+        let accessible: Vec<PathBuf> = cmdline.iter().map(|arg| root.access_path(arg)).collect();
+        let Position {
+            id,
+            intra,
+            estimator,
+            current_unlinked,
+        } = model.position(&Signals {
+            operands: &cmdline,
+            accessible: &accessible,
+            fds: &fds,
+            recursive,
+            root: &root,
+            source,
+            pid,
+            listings: &self.listings,
+            recorded: self.record_filesystem.then_some(&self.recorded),
+        })?;
+        Ok(Progress {
+            pid,
+            command: command_name(&argv)
+                .map(|name| display_path(Path::new(name)))
+                .unwrap_or_default(),
+            host_cwd: root.host_path(&cwd),
+            other_mount_namespace: root.other_mount_namespace,
+            cwd,
+            id,
+            intra,
+            args: cmdline.len(),
+            operands: accessible,
+            estimator,
+            current_unlinked,
+            time_since_start,
+            sampled_at,
+        })
+    }
+}
+
+/// Where a process is in its operands
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    /// Index of the operand being processed
+    pub id: usize,
+    /// Fraction of the current operand's tree processed since monitoring began
+    pub intra: Option<f32>,
+    /// How the position was found
+    pub estimator: &'static str,
+    /// The open directory the process is in was already unlinked
+    pub current_unlinked: bool,
+}
+
+/// What a process exposes about its position, for [`crate::model::CommandModel::position`]
+pub struct Signals<'s> {
+    /// Operands as seen by the process, normalized
+    pub operands: &'s [PathBuf],
+    /// Paths through which we can access the operands
+    pub accessible: &'s [PathBuf],
+    /// Open fds pointing to paths, as seen by the process
+    pub fds: &'s [FdEntry],
+    /// The tree of each operand is walked
+    pub recursive: bool,
+    root: &'s ProcessRoot,
+    source: &'s dyn ProcSource,
+    pid: u32,
+    listings: &'s RefCell<HashMap<u32, HashMap<PathBuf, Vec<OsString>>>>,
+    recorded: Option<&'s RefCell<FilesystemRecord>>,
+}
+
+impl Signals<'_> {
+    /// Number of operands that no longer exist, unknown if they can't be checked
+    pub fn removed_operands(&self) -> Option<usize> {
+        removed_args(self.accessible, |arg| {
+            let exists = self.source.exists(arg)?;
+            if let Some(recorded) = self.recorded {
+                recorded
+                    .borrow_mut()
+                    .existence
+                    .insert(arg.to_path_buf(), exists);
+            }
+            Ok(exists)
+        })
+        .ok()
+    }
+
+    /// Position from the deepest open directory in the furthest operand, from a given operand
+    /// on
+    pub fn open_fd(&self, first: usize) -> Option<Position> {
+        let lookup_hash: HashMap<&Path, usize> = self
+            .operands
+            .iter()
+            .enumerate()
+            .map(|(i, el)| (el.as_path(), i))
+            .collect();
+        let (id, open_dir) = self
+            .fds
+            .iter()
+            .filter_map(|entry| {
+                let mut components = entry.path.components();
+                loop {
+                    if let Some(&i) = lookup_hash.get(components.as_path()) {
+                        return Some((i, entry));
+                    }
+                    if components.next_back().is_none() {
+                        break;
+                    }
+                }
+                None
+            })
+            .filter(|(i, _)| *i >= first)
+            // Deepest open path of the furthest argument
+            .max_by_key(|(i, entry)| (*i, entry.path.components().count()))?;
+        let list_dir = |dir: &Path| {
+            let listing = self.source.list_dir(dir)?;
+            if let Some(recorded) = self.recorded {
+                recorded
+                    .borrow_mut()
+                    .listings
+                    .insert(dir.to_path_buf(), listing.clone());
+            }
+            Ok(listing)
+        };
+        let intra = self
+            .recursive
+            .then(|| {
+                let arg = &self.accessible[id];
+                let open_dir = self.root.access_path(&open_dir.path);
+                let mut listings = self.listings.borrow_mut();
+                intra_arg_progress(
+                    arg,
+                    &open_dir,
+                    listings.entry(self.pid).or_default(),
+                    list_dir,
+                )
+            })
+            .flatten();
+        Some(Position {
+            id,
+            intra,
+            estimator: "open fd",
+            current_unlinked: open_dir.deleted,
+        })
+    }
+}
+
+/// Rates and ETA derived from a progress sample
+pub struct Estimate {
+    /// Average args/s since rm started
+    pub rate: f32,
+    /// Recent args/s, when watching
+    pub recent_rate: Option<f32>,
+    pub eta: Option<Duration>,
+}
+
+impl Estimate {
+    pub fn new(progress: &Progress, recent_rate: Option<f32>) -> Self {
+        // The fraction of the current tree is only known since monitoring began, so the
+        // average since the process started only counts whole operands
+        let completed = progress.id as f32;
+        let args = progress.args as f32;
+        // Prefer the recent rate to react to the disk slowing down or speeding up
+        let eta = match recent_rate {
+            Some(rate) if rate > 0.0 => {
+                Duration::try_from_secs_f32((args - progress.position()) / rate).ok()
+            }
+            _ if completed > 0.0 => Duration::try_from_secs_f32(
+                progress.time_since_start.as_secs_f32() * ((args - completed) / completed),
+            )
+            .ok(),
+            _ => None,
+        };
+        let elapsed = progress.time_since_start.as_secs_f32();
+        Self {
+            rate: if elapsed > 0.0 {
+                completed / elapsed
+            } else {
+                0.0
+            },
+            recent_rate,
+            eta,
+        }
+    }
+}
+
+/// Exponentially weighted moving average of a rate measured over irregular intervals
+#[derive(Debug, Clone, Copy)]
+pub struct MovingAverage {
+    /// Time constant of the average: older samples weigh e times less every window
+    window: Duration,
+    value: Option<f32>,
+}
+
+impl MovingAverage {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            value: None,
+        }
+    }
+
+    /// Add a rate measured over `elapsed`, which must not be zero. The first one is taken as
+    /// is.
+    pub fn update(&mut self, rate: f32, elapsed: Duration) -> f32 {
+        let alpha = 1.0 - (-elapsed.as_secs_f32() / self.window.as_secs_f32()).exp();
+        let value = match self.value {
+            Some(value) => value + alpha * (rate - value),
+            None => rate,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    pub fn value(&self) -> Option<f32> {
+        self.value
+    }
+}
+
+/// Moving average of the args/s rate between successive samples
+pub struct RateTracker {
+    last_position: f32,
+    last_sample: Duration,
+    rate: MovingAverage,
+}
+
+impl RateTracker {
+    pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+    pub fn new(progress: &Progress) -> Self {
+        Self::with_window(progress, Self::DEFAULT_WINDOW)
+    }
+
+    pub fn with_window(progress: &Progress, window: Duration) -> Self {
+        Self {
+            last_position: progress.position(),
+            last_sample: progress.sampled_at,
+            rate: MovingAverage::new(window),
+        }
+    }
+
+    pub fn update(&mut self, progress: &Progress) -> Option<f32> {
+        let position = progress.position();
+        let elapsed = progress.sampled_at.saturating_sub(self.last_sample);
+        if !elapsed.is_zero() {
+            let instant_rate = (position - self.last_position).max(0.0) / elapsed.as_secs_f32();
+            self.rate.update(instant_rate, elapsed);
+            self.last_position = position;
+            self.last_sample = progress.sampled_at;
+        }
+        self.rate.value()
+    }
+}
+
+/// Count the arguments that no longer exist, up to the first one that still does.
+///
+/// rm processes its arguments in order, so this also tells how many were completed, even
+/// when rm is between directories or unlinking plain files it never opens. Arguments missing
+/// further on were never there, as often with `rm -f`, and don't tell anything. Fails if the
+/// existence of an argument can't be checked with `exists`.
+pub fn removed_args(
+    cmdline: &[PathBuf],
+    mut exists: impl FnMut(&Path) -> io::Result<bool>,
+) -> io::Result<usize> {
+    for (i, arg) in cmdline.iter().enumerate() {
+        if exists(arg)? {
+            return Ok(i);
+        }
+    }
+    Ok(cmdline.len())
+}
+
+/// Estimate which fraction of an argument's tree has been processed since we first looked at
+/// it, from a directory currently open inside it.
+///
+/// At each level between the argument and the open directory, we look at where
+/// the child sits in its parent's directory listing, and refine the estimate
+/// with the child's share of that level. This assumes rm walks directories in
+/// listing order.
+///
+/// rm unlinks the entries it is done with, so the child it is in is always first in the
+/// live listing of its parent. Listings are therefore taken the first time each
+/// directory is seen with `list_dir`, and kept in `listings` while the open directory is
+/// inside it. The fraction is of what was left when the argument was first listed, and
+/// unknown until then: in the call that lists it, rm is at the start of what is left.
+pub fn intra_arg_progress(
+    arg: &Path,
+    open_dir: &Path,
+    listings: &mut HashMap<PathBuf, Vec<OsString>>,
+    mut list_dir: impl FnMut(&Path) -> io::Result<Vec<OsString>>,
+) -> Option<f32> {
+    let relative = open_dir.strip_prefix(arg).ok()?;
+    // Directories left behind won't be seen again
+    listings.retain(|dir, _| open_dir.starts_with(dir));
+    if let Entry::Vacant(entry) = listings.entry(arg.to_path_buf()) {
+        entry.insert(list_dir(arg).ok()?);
+        return None;
+    }
+    let mut parent = arg.to_path_buf();
+    let mut fraction = 0.0;
+    let mut scale = 1.0;
+    for component in relative.components() {
+        let Component::Normal(name) = component else {
+            return None;
+        };
+        let listing = match listings.entry(parent.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(list_dir(&parent).ok()?),
+        };
+        let position = listing.iter().position(|entry| entry == name)?;
+        fraction += scale * position as f32 / listing.len() as f32;
+        scale /= listing.len() as f32;
+        parent.push(name);
+    }
+    Some(fraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::FixedClock,
+        procfs::{FakeProc, FakeProcess},
+    };
+    use std::time::UNIX_EPOCH;
+
+    const PID: u32 = 100;
+
+    /// An rm started 50s after boot, in the subdirectory of an operand
+    fn processes(argv: &[&str], open: &str) -> FakeProc {
+        let mut stat = vec!["0"; 50];
+        stat[0] = "D";
+        // Field 22, starttime in ticks
+        stat[19] = "5000";
+        let mut processes = FakeProc {
+            mount_namespace: "mnt:[1]".to_string(),
+            ..FakeProc::default()
+        };
+        processes.insert(
+            PID,
+            FakeProcess {
+                exe: PathBuf::from("/usr/bin/rm"),
+                cwd: PathBuf::from("/data"),
+                comm: "rm".to_string(),
+                argv: argv.iter().map(OsString::from).collect(),
+                stat: format!("{PID} (rm) {}", stat.join(" ")),
+                fds: vec![(0, PathBuf::from("/dev/null")), (3, PathBuf::from(open))],
+                ..FakeProcess::default()
+            },
+        );
+        processes
+    }
+
+    fn listing(names: &[&str]) -> Vec<OsString> {
+        names.iter().map(OsString::from).collect()
+    }
+
+    const CLOCK: FixedClock = FixedClock {
+        since_boot: Duration::from_secs(80),
+        ticks_per_second: 100,
+        wall: UNIX_EPOCH,
+    };
+
+    fn sampler(source: &FakeProc) -> Sampler<'_> {
+        let mut sampler = Sampler::new(source, &CLOCK);
+        sampler.probe_filesystem = false;
+        sampler
+    }
+
+    #[test]
+    fn position_from_open_fd() {
+        let mut source = processes(&["rm", "-r", "a", "/data/b", "c"], "/data/b/sub");
+        source
+            .listings
+            .insert(PathBuf::from("/data/b"), listing(&["x", "sub", "y", "z"]));
+        let sampler = sampler(&source);
+        let progress = sampler.sample(PID).unwrap();
+        assert_eq!(progress.command, "rm");
+        assert_eq!(progress.args, 3);
+        assert_eq!(progress.id, 1);
+        // The listing was just taken
+        assert_eq!(progress.intra, None);
+        assert_eq!(progress.estimator, "open fd");
+        assert_eq!(progress.time_since_start, Duration::from_secs(30));
+        assert_eq!(progress.sampled_at, Duration::from_secs(80));
+        assert_eq!(sampler.sample(PID).unwrap().intra, Some(0.25));
+    }
+
+    #[test]
+    fn eta_too_far() {
+        let mut source = processes(&["rm", "-r", "a", "/data/b", "c"], "/data/b/sub");
+        source
+            .listings
+            .insert(PathBuf::from("/data/b"), listing(&["x", "sub", "y", "z"]));
+        let sampler = sampler(&source);
+        sampler.sample(PID).unwrap();
+        let mut progress = sampler.sample(PID).unwrap();
+        // Whole operands only since the process started
+        assert_eq!(
+            Estimate::new(&progress, None).eta,
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            Estimate::new(&progress, Some(0.5)).eta,
+            Some(Duration::from_secs_f32(3.5))
+        );
+        // Running for ages
+        progress.time_since_start = Duration::from_secs(u64::MAX);
+        assert_eq!(Estimate::new(&progress, None).eta, None);
+        // Barely moving
+        assert_eq!(Estimate::new(&progress, Some(1e-30)).eta, None);
+    }
+
+    #[test]
+    fn listings_as_first_seen() {
+        let arg = Path::new("/data/b");
+        let mut listings = HashMap::new();
+        let mut progress = |open: &str| {
+            intra_arg_progress(arg, Path::new(open), &mut listings, |dir| {
+                Ok(match dir.to_str() {
+                    Some("/data/b") => listing(&["sub", "y", "z", "w"]),
+                    Some("/data/b/sub") => listing(&["1", "2"]),
+                    _ => listing(&["unused"]),
+                })
+            })
+        };
+        // Unknown until the argument was listed before
+        assert_eq!(progress("/data/b/sub/1"), None);
+        assert_eq!(progress("/data/b/sub/2"), Some(0.125));
+        // rm removed sub, and z is now first in the live listing
+        assert_eq!(progress("/data/b/z"), Some(0.5));
+        // Directories left behind are forgotten
+        assert!(!listings.contains_key(Path::new("/data/b/sub")));
+    }
+
+    #[test]
+    fn existence_through_source() {
+        let mut source = processes(&["rm", "a", "b", "c"], "/elsewhere");
+        for (operand, exists) in [("/data/a", false), ("/data/b", true), ("/data/c", true)] {
+            source.existence.insert(PathBuf::from(operand), exists);
+        }
+        let mut sampler = sampler(&source);
+        sampler.record_filesystem = true;
+        let progress = sampler.sample(PID).unwrap();
+        assert_eq!(progress.estimator, "existence");
+        assert_eq!(progress.id, 1);
+        // Not c, after the first operand that exists
+        let recorded = sampler.take_recorded().existence;
+        assert_eq!(
+            recorded.into_iter().collect::<Vec<_>>(),
+            [
+                (PathBuf::from("/data/a"), false),
+                (PathBuf::from("/data/b"), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_operand_ahead() {
+        let mut source = processes(&["rm", "-rf", "a", "missing", "b"], "/data/a/sub");
+        for (operand, exists) in [
+            ("/data/a", true),
+            ("/data/missing", false),
+            ("/data/b", true),
+        ] {
+            source.existence.insert(PathBuf::from(operand), exists);
+        }
+        source
+            .listings
+            .insert(PathBuf::from("/data/a"), listing(&["sub"]));
+        let progress = sampler(&source).sample(PID).unwrap();
+        assert_eq!(progress.estimator, "open fd");
+        assert_eq!(progress.id, 0);
+        // Once a is removed, rm is past the missing operand
+        source.existence.insert(PathBuf::from("/data/a"), false);
+        source.processes.get_mut(&PID).unwrap().fds.pop();
+        let progress = sampler(&source).sample(PID).unwrap();
+        assert_eq!(progress.estimator, "existence");
+        assert_eq!(progress.id, 2);
+    }
+
+    #[test]
+    fn in_chroot() {
+        let mut source = processes(&["rm", "-r", "a", "/data/b"], "/srv/jail/data/b/sub");
+        let process = source.processes.get_mut(&PID).unwrap();
+        process.root = PathBuf::from("/srv/jail");
+        process.cwd = PathBuf::from("/srv/jail/data");
+        source
+            .listings
+            .insert(PathBuf::from("/srv/jail/data/b"), listing(&["x", "sub"]));
+        let sampler = sampler(&source);
+        let progress = sampler.sample(PID).unwrap();
+        assert_eq!(progress.cwd, Path::new("/data"));
+        assert_eq!(progress.host_cwd, Some(PathBuf::from("/srv/jail/data")));
+        assert!(!progress.other_mount_namespace);
+        assert_eq!(
+            progress.operands,
+            [
+                PathBuf::from("/srv/jail/data/a"),
+                PathBuf::from("/srv/jail/data/b")
+            ]
+        );
+        assert_eq!(progress.id, 1);
+        assert_eq!(sampler.sample(PID).unwrap().intra, Some(0.5));
+    }
+
+    #[test]
+    fn in_other_mount_namespace() {
+        let mut source = processes(&["rm", "-r", "a", "/data/b"], "/data/b/sub");
+        let process = source.processes.get_mut(&PID).unwrap();
+        process.mount_namespace = "mnt:[2]".to_string();
+        process.root_access = PathBuf::from("/proc/100/root");
+        source.listings.insert(
+            PathBuf::from("/proc/100/root/data/b"),
+            listing(&["x", "sub"]),
+        );
+        let sampler = sampler(&source);
+        let progress = sampler.sample(PID).unwrap();
+        // Links are already as the process sees them
+        assert_eq!(progress.cwd, Path::new("/data"));
+        assert_eq!(
+            progress.host_cwd,
+            Some(PathBuf::from("/proc/100/root/data"))
+        );
+        assert!(progress.other_mount_namespace);
+        assert_eq!(
+            progress.operands,
+            [
+                PathBuf::from("/proc/100/root/data/a"),
+                PathBuf::from("/proc/100/root/data/b")
+            ]
+        );
+        assert_eq!(progress.id, 1);
+        assert_eq!(sampler.sample(PID).unwrap().intra, Some(0.5));
+    }
+
+    #[test]
+    fn vanished_process() {
+        assert!(matches!(
+            sampler(&FakeProc::new()).sample(PID),
+            Err(Error::ProcessVanished)
+        ));
+    }
+
+    #[test]
+    fn operand_above_root() {
+        let source = processes(&["rm", "-r", "../../x"], "/data");
+        assert!(matches!(
+            sampler(&source).sample(PID),
+            Err(Error::UnnormalizableOperand(_))
+        ));
+    }
+
+    #[test]
+    fn no_matching_fd() {
+        let source = processes(&["rm", "-r", "/data/b"], "/elsewhere");
+        assert!(matches!(
+            sampler(&source).sample(PID),
+            Err(Error::NoMatchingFd)
+        ));
+    }
+
+    #[test]
+    fn find_that_only_searches() {
+        let mut source = processes(&["find", "/data", "-name", "*.tmp"], "/data/b");
+        source.processes.get_mut(&PID).unwrap().comm = "find".to_string();
+        source.processes.get_mut(&PID).unwrap().exe = PathBuf::from("/usr/bin/find");
+        assert!(matches!(
+            sampler(&source).sample(PID),
+            Err(Error::NotDeleting)
+        ));
+    }
+
+    #[test]
+    fn chmod_that_does_not_recurse() {
+        let mut source = processes(&["chmod", "u+w", "a", "b"], "/data/b");
+        source.processes.get_mut(&PID).unwrap().comm = "chmod".to_string();
+        source.processes.get_mut(&PID).unwrap().exe = PathBuf::from("/usr/bin/chmod");
+        assert!(matches!(
+            sampler(&source).sample(PID),
+            Err(Error::NotRecursive)
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+//! Path resolution for processes in a chroot or another mount namespace
+//!
+//! The command line of a process is relative to its own root directory, while its cwd and fd
+//! links are resolved from our point of view: under the chroot directory for a chrooted
+//! process, and in the process's own namespace for one in a container. Its files are always
+//! reachable through `/proc/<pid>/root`.
+
+use crate::{error::Error, procfs::ProcSource};
+use std::path::{Path, PathBuf};
+
+/// How the paths of a process map to paths we can access
+#[derive(Debug, Clone)]
+pub struct ProcessRoot {
+    /// Root directory of the process, as we see it
+    root: PathBuf,
+    /// Directory through which we access the process's root directory
+    access: PathBuf,
+    /// The process is in another mount namespace than ours
+    pub other_mount_namespace: bool,
+}
+
+impl ProcessRoot {
+    pub fn new(source: &dyn ProcSource, pid: u32) -> Result<Self, Error> {
+        let root = source.root(pid)?;
+        // If we can't tell, assume the process isn't in a container
+        let other_mount_namespace =
+            match (source.mount_namespace(pid), source.own_mount_namespace()) {
+                (Ok(namespace), Ok(own)) => namespace != own,
+                _ => false,
+            };
+        let access = if other_mount_namespace || root != Path::new("/") {
+            source.root_access(pid)
+        } else {
+            PathBuf::from("/")
+        };
+        Ok(Self {
+            root,
+            access,
+            other_mount_namespace,
+        })
+    }
+
+    /// The process shares our view of the filesystem
+    pub fn is_host(&self) -> bool {
+        !self.other_mount_namespace && self.root == Path::new("/")
+    }
+
+    /// Convert a path we read from a link of the process to a path as seen by the process
+    pub fn to_process(&self, path: &Path) -> PathBuf {
+        if self.other_mount_namespace {
+            // Links are already resolved in the namespace of the process
+            return path.to_path_buf();
+        }
+        match path.strip_prefix(&self.root) {
+            Ok(relative) => Path::new("/").join(relative),
+            // Outside the chroot, e.g. an fd opened before chrooting
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    /// Path through which we can access a path of the process
+    pub fn access_path(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix("/") {
+            Ok(relative) => join(&self.access, relative),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    /// Path of a process path for the host, if it differs
+    pub fn host_path(&self, path: &Path) -> Option<PathBuf> {
+        if self.is_host() {
+            return None;
+        }
+        let relative = path.strip_prefix("/").ok()?;
+        Some(if self.other_mount_namespace {
+            join(&self.access, relative)
+        } else {
+            join(&self.root, relative)
+        })
+    }
+}
+
+/// Join without adding a trailing slash for an empty relative path
+fn join(base: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(relative)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::procfs::{FakeProc, FakeProcess};
+
+    fn root(process: FakeProcess) -> ProcessRoot {
+        let mut source = FakeProc {
+            mount_namespace: "mnt:[1]".to_string(),
+            ..FakeProc::default()
+        };
+        source.insert(1, process);
+        ProcessRoot::new(&source, 1).unwrap()
+    }
+
+    #[test]
+    fn host() {
+        let root = root(FakeProcess::default());
+        assert!(root.is_host());
+        assert_eq!(root.to_process(Path::new("/data/a")), Path::new("/data/a"));
+        assert_eq!(root.access_path(Path::new("/data/a")), Path::new("/data/a"));
+        assert_eq!(root.host_path(Path::new("/data/a")), None);
+    }
+
+    #[test]
+    fn chroot() {
+        let root = root(FakeProcess {
+            root: PathBuf::from("/srv/jail"),
+            ..FakeProcess::default()
+        });
+        assert!(!root.is_host());
+        assert_eq!(
+            root.to_process(Path::new("/srv/jail/data")),
+            Path::new("/data")
+        );
+        assert_eq!(root.to_process(Path::new("/srv/jail")), Path::new("/"));
+        // Opened before chrooting
+        assert_eq!(
+            root.to_process(Path::new("/var/log")),
+            Path::new("/var/log")
+        );
+        assert_eq!(root.access_path(Path::new("/")), Path::new("/srv/jail"));
+        assert_eq!(
+            root.host_path(Path::new("/data")),
+            Some(PathBuf::from("/srv/jail/data"))
+        );
+    }
+
+    #[test]
+    fn other_mount_namespace() {
+        let root = root(FakeProcess {
+            mount_namespace: "mnt:[2]".to_string(),
+            root_access: PathBuf::from("/proc/1/root"),
+            ..FakeProcess::default()
+        });
+        assert!(root.other_mount_namespace);
+        assert_eq!(root.to_process(Path::new("/data")), Path::new("/data"));
+        assert_eq!(
+            root.access_path(Path::new("/data")),
+            Path::new("/proc/1/root/data")
+        );
+        assert_eq!(
+            root.host_path(Path::new("/")),
+            Some(PathBuf::from("/proc/1/root"))
+        );
+    }
+}
@@ -0,0 +1,546 @@
+//! Recording of the procfs data we read, to replay estimations offline
+//!
+//! A trace is a text file. After a `progressrm-trace <version>` header, each frame starts with
+//! a `frame` line holding the clock readings, followed by the processes captured: a `pid`
+//! line, then one line per field, argument, fd and fd detail. Values go until the end of the
+//! line, and bytes that aren't printable ASCII are escaped as `\xNN`, so that arbitrary paths
+//! fit.
+//!
+//! The filesystem changes while processes work on it, so what estimators look at there is
+//! recorded too, before the processes of the frame: the directory listings, the first time they
+//! are taken, as a `dir` line followed by one `entry` line per entry, and the operands checked
+//! for existence, as an `exists` or `missing` line.
+
+use crate::{
+    clock::{Clock, FixedClock},
+    error::Error,
+    model::Registry,
+    procfs::{
+        FakeProc, FakeProcess, PidIterator, ProcSource, ProcessMatch, parent_pid, read_cmdline,
+    },
+    progress::{FilesystemRecord, Sampler},
+    xargs::is_xargs,
+};
+use std::{
+    cell::RefCell,
+    ffi::OsString,
+    io::{self, BufRead, Write},
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+const HEADER: &str = "progressrm-trace";
+const VERSION: u32 = 1;
+
+/// The processes we saw at one point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Clock readings when the frame was captured
+    pub clock: FixedClock,
+    pub processes: FakeProc,
+}
+
+impl Frame {
+    /// Capture the data of the processes that match
+    pub fn capture(
+        source: &dyn ProcSource,
+        clock: &dyn Clock,
+        models: &Registry,
+        process_match: &[ProcessMatch],
+    ) -> Result<Self, Error> {
+        let clock = FixedClock {
+            since_boot: clock.since_boot()?,
+            ticks_per_second: clock.ticks_per_second()?,
+            wall: clock.wall(),
+        };
+        let mut processes = FakeProc {
+            mount_namespace: lossy(source.own_mount_namespace()),
+            ..FakeProc::default()
+        };
+        for pid in PidIterator::new(source, models, process_match)? {
+            match capture_process(source, pid) {
+                Ok(process) => processes.insert(pid, process),
+                // Exited or unreadable processes are left out
+                Err(_) => continue,
+            }
+        }
+        // xargs jobs are estimated from the parent of the rm batches
+        let parents: Vec<u32> = processes
+            .processes
+            .keys()
+            .filter_map(|&pid| parent_pid(source, pid).ok())
+            .filter(|parent| !processes.processes.contains_key(parent))
+            .filter(|&parent| is_xargs(source, parent))
+            .collect();
+        for parent in parents {
+            if let Ok(process) = capture_process(source, parent) {
+                processes.insert(parent, process);
+            }
+        }
+        Ok(Self { clock, processes })
+    }
+
+    /// Add what the sampler looks at on the filesystem for the processes of the frame: the
+    /// directory listings it didn't take before and the existence of operands, so that
+    /// replaying the frames sees the same
+    pub fn record_filesystem(&mut self, source: &LiveFilesystem, sampler: &Sampler) {
+        source.processes.replace(self.processes.clone());
+        for &pid in self.processes.processes.keys() {
+            // Only what is looked at is wanted
+            let _ = sampler.sample(pid);
+        }
+        let FilesystemRecord {
+            listings,
+            existence,
+        } = sampler.take_recorded();
+        self.processes.listings = listings;
+        self.processes.existence = existence;
+    }
+}
+
+/// Processes of the frame being recorded, with the live filesystem. Sampling through it looks
+/// at what a replay of the frame needs.
+pub struct LiveFilesystem<'a> {
+    processes: RefCell<FakeProc>,
+    live: &'a dyn ProcSource,
+}
+
+impl<'a> LiveFilesystem<'a> {
+    pub fn new(live: &'a dyn ProcSource) -> Self {
+        Self {
+            processes: RefCell::default(),
+            live,
+        }
+    }
+}
+
+impl ProcSource for LiveFilesystem<'_> {
+    fn pids(&self) -> io::Result<Vec<u32>> {
+        self.processes.borrow().pids()
+    }
+    fn exe(&self, pid: u32) -> io::Result<PathBuf> {
+        self.processes.borrow().exe(pid)
+    }
+    fn cwd(&self, pid: u32) -> io::Result<PathBuf> {
+        self.processes.borrow().cwd(pid)
+    }
+    fn comm(&self, pid: u32) -> io::Result<Vec<u8>> {
+        self.processes.borrow().comm(pid)
+    }
+    fn cmdline(&self, pid: u32) -> io::Result<Vec<u8>> {
+        self.processes.borrow().cmdline(pid)
+    }
+    fn stat(&self, pid: u32) -> io::Result<String> {
+        self.processes.borrow().stat(pid)
+    }
+    fn io(&self, pid: u32) -> io::Result<String> {
+        self.processes.borrow().io(pid)
+    }
+    fn wchan(&self, pid: u32) -> io::Result<String> {
+        self.processes.borrow().wchan(pid)
+    }
+    fn ioprio(&self, pid: u32) -> io::Result<i32> {
+        self.processes.borrow().ioprio(pid)
+    }
+    fn fds(&self, pid: u32) -> io::Result<Vec<(u32, PathBuf)>> {
+        self.processes.borrow().fds(pid)
+    }
+    fn fdinfo(&self, pid: u32, fd: u32) -> io::Result<String> {
+        self.processes.borrow().fdinfo(pid, fd)
+    }
+    fn fd_size(&self, pid: u32, fd: u32) -> io::Result<Option<u64>> {
+        self.processes.borrow().fd_size(pid, fd)
+    }
+    fn root(&self, pid: u32) -> io::Result<PathBuf> {
+        self.processes.borrow().root(pid)
+    }
+    fn mount_namespace(&self, pid: u32) -> io::Result<PathBuf> {
+        self.processes.borrow().mount_namespace(pid)
+    }
+    fn own_mount_namespace(&self) -> io::Result<PathBuf> {
+        self.processes.borrow().own_mount_namespace()
+    }
+    fn root_access(&self, pid: u32) -> PathBuf {
+        self.processes.borrow().root_access(pid)
+    }
+    fn list_dir(&self, dir: &Path) -> io::Result<Vec<OsString>> {
+        self.live.list_dir(dir)
+    }
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        self.live.exists(path)
+    }
+}
+
+fn capture_process(source: &dyn ProcSource, pid: u32) -> io::Result<FakeProcess> {
+    let fds = source.fds(pid)?;
+    Ok(FakeProcess {
+        // Only used to match processes, which replay doesn't need
+        exe: source.exe(pid).unwrap_or_default(),
+        cwd: source.cwd(pid)?,
+        comm: String::from_utf8_lossy(&source.comm(pid)?)
+            .trim_end()
+            .to_string(),
+        argv: read_cmdline(source, pid)?,
+        stat: source.stat(pid)?,
+        // Diagnostics only, and io is only readable for our own processes
+        io: source.io(pid).unwrap_or_default(),
+        wchan: source.wchan(pid).unwrap_or_default(),
+        ioprio: source.ioprio(pid).unwrap_or_default(),
+        fdinfo: fds
+            .iter()
+            .filter_map(|&(fd, _)| Some((fd, source.fdinfo(pid, fd).ok()?)))
+            .collect(),
+        fd_sizes: fds
+            .iter()
+            .filter_map(|&(fd, _)| Some((fd, source.fd_size(pid, fd).ok()??)))
+            .collect(),
+        fds,
+        root: source.root(pid)?,
+        root_access: source.root_access(pid),
+        mount_namespace: lossy(source.mount_namespace(pid)),
+    })
+}
+
+fn lossy(path: io::Result<PathBuf>) -> String {
+    path.map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+pub struct TraceWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        writeln!(out, "{HEADER} {VERSION}")?;
+        Ok(Self { out })
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        let FixedClock {
+            since_boot,
+            ticks_per_second,
+            wall,
+        } = frame.clock;
+        let wall = wall.duration_since(UNIX_EPOCH).unwrap_or_default();
+        writeln!(
+            self.out,
+            "frame {} {ticks_per_second} {} {}",
+            format_duration(since_boot),
+            format_duration(wall),
+            escape(frame.processes.mount_namespace.as_bytes()),
+        )?;
+        for (dir, entries) in &frame.processes.listings {
+            writeln!(self.out, "dir {}", escape(dir.as_os_str().as_bytes()))?;
+            for entry in entries {
+                writeln!(self.out, "entry {}", escape(entry.as_bytes()))?;
+            }
+        }
+        for (path, &exists) in &frame.processes.existence {
+            let key = if exists { "exists" } else { "missing" };
+            writeln!(self.out, "{key} {}", escape(path.as_os_str().as_bytes()))?;
+        }
+        for (pid, process) in &frame.processes.processes {
+            writeln!(self.out, "pid {pid}")?;
+            writeln!(
+                self.out,
+                "exe {}",
+                escape(process.exe.as_os_str().as_bytes())
+            )?;
+            writeln!(
+                self.out,
+                "cwd {}",
+                escape(process.cwd.as_os_str().as_bytes())
+            )?;
+            writeln!(self.out, "comm {}", escape(process.comm.as_bytes()))?;
+            for arg in &process.argv {
+                writeln!(self.out, "arg {}", escape(arg.as_bytes()))?;
+            }
+            writeln!(self.out, "stat {}", escape(process.stat.as_bytes()))?;
+            writeln!(self.out, "io {}", escape(process.io.as_bytes()))?;
+            writeln!(self.out, "wchan {}", escape(process.wchan.as_bytes()))?;
+            writeln!(self.out, "ioprio {}", process.ioprio)?;
+            for (fd, link) in &process.fds {
+                writeln!(self.out, "fd {fd} {}", escape(link.as_os_str().as_bytes()))?;
+            }
+            for (fd, fdinfo) in &process.fdinfo {
+                writeln!(self.out, "fdinfo {fd} {}", escape(fdinfo.as_bytes()))?;
+            }
+            for (fd, size) in &process.fd_sizes {
+                writeln!(self.out, "size {fd} {size}")?;
+            }
+            writeln!(
+                self.out,
+                "root {}",
+                escape(process.root.as_os_str().as_bytes())
+            )?;
+            writeln!(
+                self.out,
+                "access {}",
+                escape(process.root_access.as_os_str().as_bytes())
+            )?;
+            writeln!(
+                self.out,
+                "mnt {}",
+                escape(process.mount_namespace.as_bytes())
+            )?;
+        }
+        self.out.flush()
+    }
+}
+
+/// Read all the frames of a trace
+pub fn read_trace(input: impl BufRead) -> Result<Vec<Frame>, Error> {
+    let mut lines = input.lines();
+    let header = lines.next().transpose().map_err(Error::Io)?;
+    if header.as_deref() != Some(&format!("{HEADER} {VERSION}")) {
+        return Err(Error::Parse(format!(
+            "not a {HEADER} file, or not version {VERSION}"
+        )));
+    }
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut pid = None;
+    let mut dir = None;
+    for (number, line) in lines.enumerate() {
+        let line = line.map_err(Error::Io)?;
+        // Header is line 1
+        let parse_error = |what: &str| Error::Parse(format!("line {}: {what}", number + 2));
+        let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+        if key == "frame" {
+            let fields: Vec<&str> = value.splitn(4, ' ').collect();
+            let [since_boot, ticks_per_second, wall, mount_namespace] = fields[..] else {
+                return Err(parse_error("invalid frame"));
+            };
+            frames.push(Frame {
+                clock: FixedClock {
+                    since_boot: parse_duration(since_boot).ok_or_else(|| parse_error("time"))?,
+                    ticks_per_second: ticks_per_second
+                        .parse()
+                        .map_err(|_| parse_error("ticks per second"))?,
+                    wall: UNIX_EPOCH
+                        + parse_duration(wall).ok_or_else(|| parse_error("wall time"))?,
+                },
+                processes: FakeProc {
+                    mount_namespace: unescape_string(mount_namespace)
+                        .ok_or_else(|| parse_error("namespace"))?,
+                    ..FakeProc::default()
+                },
+            });
+            pid = None;
+            dir = None;
+            continue;
+        }
+        let frame = frames
+            .last_mut()
+            .ok_or_else(|| parse_error("data before first frame"))?;
+        let path = || {
+            unescape(value)
+                .map(|bytes| PathBuf::from(OsString::from_vec(bytes)))
+                .ok_or_else(|| parse_error("invalid escape"))
+        };
+        if key == "dir" {
+            let path = path()?;
+            frame.processes.listings.insert(path.clone(), Vec::new());
+            dir = Some(path);
+            continue;
+        }
+        if key == "entry" {
+            let entries = dir
+                .as_ref()
+                .and_then(|dir| frame.processes.listings.get_mut(dir))
+                .ok_or_else(|| parse_error("entry before dir"))?;
+            entries.push(path()?.into_os_string());
+            continue;
+        }
+        if key == "exists" || key == "missing" {
+            frame.processes.existence.insert(path()?, key == "exists");
+            continue;
+        }
+        if key == "pid" {
+            let p = value.parse().map_err(|_| parse_error("invalid pid"))?;
+            frame.processes.insert(p, FakeProcess::default());
+            pid = Some(p);
+            continue;
+        }
+        let process = pid
+            .and_then(|pid| frame.processes.processes.get_mut(&pid))
+            .ok_or_else(|| parse_error("process data before pid"))?;
+        let string = || unescape_string(value).ok_or_else(|| parse_error("invalid escape"));
+        match key {
+            "exe" => process.exe = path()?,
+            "cwd" => process.cwd = path()?,
+            "comm" => process.comm = string()?,
+            "arg" => process.argv.push(path()?.into_os_string()),
+            "stat" => process.stat = string()?,
+            "io" => process.io = string()?,
+            "wchan" => process.wchan = string()?,
+            "ioprio" => {
+                process.ioprio = value.parse().map_err(|_| parse_error("invalid ioprio"))?
+            }
+            "fd" => {
+                let (fd, link) = value
+                    .split_once(' ')
+                    .ok_or_else(|| parse_error("invalid fd"))?;
+                let fd = fd.parse().map_err(|_| parse_error("invalid fd"))?;
+                let link = unescape(link).ok_or_else(|| parse_error("invalid escape"))?;
+                process
+                    .fds
+                    .push((fd, PathBuf::from(OsString::from_vec(link))));
+            }
+            "fdinfo" => {
+                let (fd, fdinfo) = value
+                    .split_once(' ')
+                    .ok_or_else(|| parse_error("invalid fdinfo"))?;
+                let fd = fd.parse().map_err(|_| parse_error("invalid fdinfo"))?;
+                let fdinfo =
+                    unescape_string(fdinfo).ok_or_else(|| parse_error("invalid escape"))?;
+                process.fdinfo.push((fd, fdinfo));
+            }
+            "size" => {
+                let (fd, size) = value
+                    .split_once(' ')
+                    .ok_or_else(|| parse_error("invalid size"))?;
+                process.fd_sizes.push((
+                    fd.parse().map_err(|_| parse_error("invalid size"))?,
+                    size.parse().map_err(|_| parse_error("invalid size"))?,
+                ));
+            }
+            "root" => process.root = path()?,
+            "access" => process.root_access = path()?,
+            "mnt" => process.mount_namespace = string()?,
+            _ => return Err(parse_error("unknown field")),
+        }
+    }
+    Ok(frames)
+}
+
+fn format_duration(d: Duration) -> String {
+    format!("{}.{:09}", d.as_secs(), d.subsec_nanos())
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (secs, nanos) = s.split_once('.')?;
+    Some(Duration::new(secs.parse().ok()?, nanos.parse().ok()?))
+}
+
+/// Escape bytes so that they fit on a line
+fn escape(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for &b in bytes {
+        if (b.is_ascii_graphic() || b == b' ') && b != b'\\' {
+            out.push(b as char);
+        } else {
+            out += &format!("\\x{b:02x}");
+        }
+    }
+    out
+}
+
+fn unescape(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'\\' {
+            if bytes.next()? != b'x' {
+                return None;
+            }
+            let hex = [bytes.next()?, bytes.next()?];
+            out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            out.push(b);
+        }
+    }
+    Some(out)
+}
+
+fn unescape_string(s: &str) -> Option<String> {
+    String::from_utf8(unescape(s)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(since_boot: u64) -> Frame {
+        let mut processes = FakeProc {
+            mount_namespace: "mnt:[4026531841]".to_string(),
+            ..FakeProc::default()
+        };
+        processes.listings.insert(
+            PathBuf::from("/data/b\\x"),
+            vec![
+                OsString::from("sub"),
+                OsString::from_vec(b"caf\xe9".to_vec()),
+            ],
+        );
+        processes
+            .listings
+            .insert(PathBuf::from("/empty"), Vec::new());
+        processes
+            .existence
+            .insert(PathBuf::from("/data/b\\x"), true);
+        processes
+            .existence
+            .insert(PathBuf::from("/data/a b"), false);
+        processes.insert(
+            1234,
+            FakeProcess {
+                exe: PathBuf::from("/usr/bin/rm"),
+                cwd: PathBuf::from("/home/user name"),
+                comm: "rm".to_string(),
+                argv: vec![
+                    OsString::from("rm"),
+                    OsString::from("-r"),
+                    OsString::from_vec(b"/data/\n\xff".to_vec()),
+                ],
+                stat: "1234 (rm) D 1 1234 1234 0 -1".to_string(),
+                io: "syscr: 12\nsyscw: 0\n".to_string(),
+                wchan: "io_schedule".to_string(),
+                ioprio: 0x6007,
+                fds: vec![
+                    (0, PathBuf::from("/dev/pts/0")),
+                    (3, PathBuf::from("/data/b\\x/sub (deleted)")),
+                ],
+                fdinfo: vec![(3, "pos:\t0\nflags:\t0200000\n".to_string())],
+                fd_sizes: vec![(0, 0)],
+                root: PathBuf::from("/"),
+                root_access: PathBuf::from("/proc/1234/root"),
+                mount_namespace: String::new(),
+            },
+        );
+        Frame {
+            clock: FixedClock {
+                since_boot: Duration::new(since_boot, 123_456_789),
+                ticks_per_second: 100,
+                wall: UNIX_EPOCH + Duration::new(1_792_220_709, 5),
+            },
+            processes,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let frames = vec![frame(100), frame(102)];
+        let mut trace = Vec::new();
+        let mut writer = TraceWriter::new(&mut trace).unwrap();
+        for frame in &frames {
+            writer.write_frame(frame).unwrap();
+        }
+        assert_eq!(read_trace(trace.as_slice()).unwrap(), frames);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        assert!(read_trace("progressrm-trace 0\n".as_bytes()).is_err());
+        assert!(read_trace("".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_data_before_frame() {
+        let trace = format!("{HEADER} {VERSION}\npid 1\n");
+        assert!(read_trace(trace.as_bytes()).is_err());
+        let trace = format!("{HEADER} {VERSION}\nframe 1.000000000 100 0.000000000 mnt\nentry a\n");
+        assert!(read_trace(trace.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Progress of a whole `xargs rm` job
+//!
+//! xargs runs rm on batches of its arguments, so each rm only knows about its own batch and
+//! soon looks almost done. xargs reads its arguments sequentially, so when they come from a
+//! file, the position in that file tells how far the whole job is.
+
+use crate::{
+    clock::Clock,
+    cmdline::XargsInvocation,
+    error::Error,
+    path::normalize_lexically,
+    procfs::{
+        FdEntry, FdInfo, FdIterator, ProcSource, command_args, command_name,
+        process_time_since_start, read_cmdline,
+    },
+    root::ProcessRoot,
+};
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::PathBuf, time::Duration};
+
+/// The process is xargs
+pub fn is_xargs(source: &dyn ProcSource, pid: u32) -> bool {
+    read_cmdline(source, pid).is_ok_and(|argv| command_name(&argv) == Some(OsStr::new("xargs")))
+}
+
+/// Where xargs is in its input
+#[derive(Debug, Clone)]
+pub struct XargsJob {
+    pub pid: u32,
+    /// Input file as seen by the process, or the link of a pipe
+    pub input: PathBuf,
+    /// Bytes of input read, a buffer ahead of the arguments passed to rm so far
+    pub position: u64,
+    /// Unknown if the input isn't a regular file
+    pub size: Option<u64>,
+    pub time_since_start: Duration,
+}
+
+impl XargsJob {
+    /// Read the position of xargs in its input: the `-a` file if given, stdin otherwise
+    pub fn sample(source: &dyn ProcSource, clock: &dyn Clock, pid: u32) -> Result<Self, Error> {
+        let root = ProcessRoot::new(source, pid)?;
+        let argv = read_cmdline(source, pid)?;
+        let invocation = XargsInvocation::parse(command_args(&argv));
+        let (fd, input) = match invocation.arg_file {
+            Some(file) => {
+                let cwd = root.to_process(&source.cwd(pid)?);
+                let file = normalize_lexically(&cwd.join(&file))
+                    .map_err(|_| Error::UnnormalizableOperand(file))?;
+                let entry = FdIterator::new(source, pid)?
+                    .find(|entry| root.to_process(&entry.path) == file)
+                    .ok_or(Error::NoMatchingFd)?;
+                (entry.fd, file)
+            }
+            None => {
+                let (_, link) = source
+                    .fds(pid)?
+                    .into_iter()
+                    .find(|(fd, _)| *fd == 0)
+                    .ok_or(Error::NoMatchingFd)?;
+                // Pipes and sockets have links like `pipe:[1234]`
+                let input = if link.as_os_str().as_bytes().starts_with(b"/") {
+                    let entry = FdEntry::from_link(0, link);
+                    root.to_process(&entry.path)
+                } else {
+                    link
+                };
+                (0, input)
+            }
+        };
+        Ok(Self {
+            pid,
+            input,
+            position: FdInfo::of(source, pid, fd)?.pos,
+            size: source.fd_size(pid, fd)?,
+            time_since_start: process_time_since_start(source, clock, pid)?,
+        })
+    }
+
+    /// Fraction of the input read, if it is a regular file
+    pub fn fraction(&self) -> Option<f32> {
+        let size = self.size.filter(|&size| size > 0)?;
+        Some((self.position as f32 / size as f32).min(1.0))
+    }
+
+    /// Time left at the average rate since xargs started
+    pub fn eta(&self) -> Option<Duration> {
+        let fraction = self.fraction().filter(|&fraction| fraction > 0.0)?;
+        Duration::try_from_secs_f32(
+            self.time_since_start.as_secs_f32() * ((1.0 - fraction) / fraction),
+        )
+        .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::FixedClock,
+        procfs::{FakeProc, FakeProcess},
+    };
+    use std::{ffi::OsString, path::Path, time::UNIX_EPOCH};
+
+    const PID: u32 = 200;
+
+    const CLOCK: FixedClock = FixedClock {
+        since_boot: Duration::from_secs(80),
+        ticks_per_second: 100,
+        wall: UNIX_EPOCH,
+    };
+
+    /// An xargs started 50s after boot, reading its input on `fd`
+    fn xargs(argv: &[&str], fd: u32, link: &str) -> FakeProc {
+        let mut stat = vec!["0"; 50];
+        stat[0] = "S";
+        // Field 22, starttime in ticks
+        stat[19] = "5000";
+        let mut source = FakeProc::new();
+        source.insert(
+            PID,
+            FakeProcess {
+                exe: PathBuf::from("/usr/bin/xargs"),
+                cwd: PathBuf::from("/data"),
+                comm: "xargs".to_string(),
+                argv: argv.iter().map(OsString::from).collect(),
+                stat: format!("{PID} (xargs) {}", stat.join(" ")),
+                fds: vec![(fd, PathBuf::from(link))],
+                fdinfo: vec![(fd, "pos:\t100\nflags:\t0100000\n".to_string())],
+                ..FakeProcess::default()
+            },
+        );
+        source
+    }
+
+    #[test]
+    fn from_arg_file() {
+        let mut source = xargs(&["xargs", "-0", "-a", "list", "rm"], 3, "/data/list");
+        source.processes.get_mut(&PID).unwrap().fd_sizes = vec![(3, 400)];
+        assert!(is_xargs(&source, PID));
+        let job = XargsJob::sample(&source, &CLOCK, PID).unwrap();
+        assert_eq!(job.input, Path::new("/data/list"));
+        assert_eq!(job.position, 100);
+        assert_eq!(job.size, Some(400));
+        assert_eq!(job.fraction(), Some(0.25));
+        assert_eq!(job.eta(), Some(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn arg_file_not_open() {
+        let source = xargs(&["xargs", "-a", "list", "rm"], 3, "/data/other");
+        assert!(matches!(
+            XargsJob::sample(&source, &CLOCK, PID),
+            Err(Error::NoMatchingFd)
+        ));
+    }
+
+    #[test]
+    fn from_stdin() {
+        let mut source = xargs(&["xargs", "-0", "rm"], 0, "/data/list");
+        source.processes.get_mut(&PID).unwrap().fd_sizes = vec![(0, 400)];
+        let job = XargsJob::sample(&source, &CLOCK, PID).unwrap();
+        assert_eq!(job.input, Path::new("/data/list"));
+        assert_eq!(job.fraction(), Some(0.25));
+    }
+
+    #[test]
+    fn from_pipe() {
+        let source = xargs(&["xargs", "-0", "rm"], 0, "pipe:[1234]");
+        let job = XargsJob::sample(&source, &CLOCK, PID).unwrap();
+        assert_eq!(job.input, Path::new("pipe:[1234]"));
+        assert_eq!(job.position, 100);
+        assert_eq!(job.size, None);
+        assert_eq!(job.fraction(), None);
+        assert_eq!(job.eta(), None);
+    }
+}